@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+/// Describes the current attempt of a test, passed to the `after_each` and `cleanup` hooks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestContext {
+    name: &'static str,
+    local_id: usize,
+    attempt: usize,
+    duration: Duration,
+    panic_message: Option<String>,
+}
+
+impl TestContext {
+    #[doc(hidden)]
+    pub fn new(
+        name: &'static str,
+        local_id: usize,
+        attempt: usize,
+        duration: Duration,
+        panic_message: Option<String>,
+    ) -> Self {
+        Self {
+            name,
+            local_id,
+            attempt,
+            duration,
+            panic_message,
+        }
+    }
+
+    /// The name of the test, e.g. `my_test` or `my_test::case_1` for parameterized tests.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The identifier of the local state of the test.
+    pub fn local_id(&self) -> usize {
+        self.local_id
+    }
+
+    /// The number of the current attempt, starting at 1 and increasing with each retry.
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// The time elapsed since the current attempt started, including its `before_each` hooks.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The message the test panicked with, if it failed.
+    pub fn panic_message(&self) -> Option<&str> {
+        self.panic_message.as_deref()
+    }
+}
@@ -0,0 +1,25 @@
+//! Items used by the code generated from the `suitest` macros.
+
+mod context;
+mod exec;
+mod filter;
+mod panic;
+mod split;
+mod state;
+mod timeout;
+
+pub use futures_util;
+pub use once_cell;
+pub use once_cell::sync::OnceCell;
+
+pub use context::TestTracker;
+pub use exec::{join_tasks, limit, WorkerPool};
+pub use filter::TestFilter;
+pub use panic::{
+    expect_panic, fail, into_panic, is_setup_failure, outcome, panic_message, retry, retry_async,
+    setup, setup_async, unwrap_result, ChildResults, SetupFailed,
+};
+pub use split::SplitSuite;
+pub use state::{lock_blocking, Shared, StateMap, StateMut};
+pub use timeout::{run_timed, spawn_timed, timeout, TimedHandle};
+pub use tokio::sync::Semaphore;
@@ -0,0 +1,68 @@
+use super::panic::{panic_message, SetupFailed};
+use crate::TestContext;
+use std::{
+    any::Any,
+    sync::{Mutex, PoisonError},
+    time::Instant,
+};
+
+/// Tracks the attempts of a test to provide the [TestContext] to its hooks.
+pub struct TestTracker {
+    name: &'static str,
+    attempt: Mutex<Attempt>,
+}
+
+struct Attempt {
+    number: usize,
+    start: Option<Instant>,
+    panic_message: Option<String>,
+}
+
+impl TestTracker {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            attempt: Mutex::new(Attempt {
+                number: 0,
+                start: None,
+                panic_message: None,
+            }),
+        }
+    }
+
+    /// Mark the start of a new attempt of the test.
+    pub fn start(&self) {
+        let mut attempt = self.attempt.lock().unwrap_or_else(PoisonError::into_inner);
+        attempt.number += 1;
+        attempt.start = Some(Instant::now());
+        attempt.panic_message = None;
+    }
+
+    /// Record the panic the current attempt failed with.
+    pub fn fail(&self, payload: &(dyn Any + Send)) {
+        let payload = match payload.downcast_ref::<SetupFailed>() {
+            Some(setup) => &*setup.0,
+            None => payload,
+        };
+        let msg = panic_message(payload).unwrap_or("Box<dyn Any>");
+        self.attempt
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .panic_message = Some(msg.to_string());
+    }
+
+    /// The context of the current attempt.
+    pub fn context(&self, local_id: usize) -> TestContext {
+        let attempt = self.attempt.lock().unwrap_or_else(PoisonError::into_inner);
+        TestContext::new(
+            self.name,
+            local_id,
+            attempt.number,
+            attempt
+                .start
+                .map(|start| start.elapsed())
+                .unwrap_or_default(),
+            attempt.panic_message.clone(),
+        )
+    }
+}
@@ -0,0 +1,143 @@
+use super::timeout::spawn_timed;
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use tokio::{
+    sync::Semaphore,
+    task::{JoinError, JoinHandle},
+};
+
+/// Await the spawned test tasks, identified by their local IDs. The results are returned
+/// in the order of the IDs.
+///
+/// If `fail_fast` is set, the remaining tasks are aborted as soon as one of them panics
+/// and their results contain a cancelled [JoinError].
+pub async fn join_tasks(
+    tasks: Vec<(usize, JoinHandle<()>)>,
+    fail_fast: bool,
+) -> Vec<(usize, Result<(), JoinError>)> {
+    let aborts = tasks
+        .iter()
+        .map(|(_, handle)| handle.abort_handle())
+        .collect::<Vec<_>>();
+
+    let mut pending = tasks
+        .into_iter()
+        .map(|(id, handle)| async move { (id, handle.await) })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut results = Vec::with_capacity(pending.len());
+
+    while let Some((id, result)) = pending.next().await {
+        if fail_fast && result.as_ref().is_err_and(JoinError::is_panic) {
+            aborts.iter().for_each(|handle| handle.abort());
+        }
+        results.push((id, result));
+    }
+
+    results.sort_by_key(|(id, _)| *id);
+    results
+}
+
+/// Await the future once a permit of the semaphore is acquired, limiting the number
+/// of futures running at the same time. The permit is held until the future completes.
+pub async fn limit<F: Future>(semaphore: Arc<Semaphore>, future: F) -> F::Output {
+    let _permit = semaphore.acquire().await.expect("test semaphore closed");
+    future.await
+}
+
+/// A sync task queued in a [WorkerPool].
+struct PoolTask {
+    id: usize,
+    name: &'static str,
+    timeout: Option<Duration>,
+    task: Box<dyn FnOnce() + Send + 'static>,
+}
+
+/// Runs sync tasks with at most `workers` of them running at the same time.
+///
+/// Each task is still executed on its own thread so it can time out without holding
+/// up the worker. The thread of a timed out task is left running while the worker moves
+/// on, so it no longer counts towards the limit.
+pub struct WorkerPool {
+    workers: usize,
+    fail_fast: bool,
+    tasks: Vec<PoolTask>,
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize, fail_fast: bool) -> Self {
+        Self {
+            workers,
+            fail_fast,
+            tasks: vec![],
+        }
+    }
+
+    /// Queue the task identified by its local ID.
+    pub fn push<F>(&mut self, id: usize, name: &'static str, timeout: Option<Duration>, task: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.tasks.push(PoolTask {
+            id,
+            name,
+            timeout,
+            task: Box::new(task),
+        });
+    }
+
+    /// Run all queued tasks and return their results in the order of their IDs, along
+    /// with the names of the tasks that never started because `fail_fast` is set and
+    /// one of the tasks failed.
+    pub fn run(self) -> (Vec<(usize, thread::Result<()>)>, Vec<&'static str>) {
+        let workers = self.workers.min(self.tasks.len());
+        let queue = Mutex::new(VecDeque::from(self.tasks));
+        let failed = AtomicBool::new(false);
+        let results = Mutex::new(vec![]);
+        let skipped = Mutex::new(vec![]);
+
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| loop {
+                    let Some(PoolTask {
+                        id,
+                        name,
+                        timeout,
+                        task,
+                    }) = queue.lock().unwrap().pop_front()
+                    else {
+                        break;
+                    };
+
+                    if self.fail_fast && failed.load(Ordering::SeqCst) {
+                        skipped.lock().unwrap().push((id, name));
+                        continue;
+                    }
+
+                    let result = spawn_timed(name, timeout, task).join();
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    results.lock().unwrap().push((id, result));
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(id, _)| *id);
+
+        let mut skipped = skipped.into_inner().unwrap();
+        skipped.sort_by_key(|(id, _)| *id);
+
+        (results, skipped.into_iter().map(|(_, name)| name).collect())
+    }
+}
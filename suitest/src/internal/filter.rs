@@ -0,0 +1,99 @@
+/// Mirrors the test selection libtest performs based on its command line arguments.
+///
+/// Used in `split` suites to determine how many of the suite's tests will run in
+/// the current process, since libtest does not expose this information.
+#[derive(Debug, Default)]
+pub struct TestFilter {
+    /// Positional filters, a test is selected if it matches any of them.
+    filters: Vec<String>,
+
+    /// Filters passed with `--skip`.
+    skip: Vec<String>,
+
+    /// Whether `--exact` was passed.
+    exact: bool,
+
+    /// Whether `--ignored` was passed.
+    ignored: bool,
+
+    /// Whether `--include-ignored` was passed.
+    include_ignored: bool,
+}
+
+/// libtest options that consume the following argument as their value.
+const VALUE_OPTIONS: &[&str] = &[
+    "--color",
+    "--format",
+    "--logfile",
+    "--shuffle-seed",
+    "--skip",
+    "--test-threads",
+    "-Z",
+];
+
+impl TestFilter {
+    /// Parse the filter from the arguments the current process was started with.
+    pub fn from_env() -> Self {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parse the filter from libtest arguments, excluding the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Self {
+        let mut filter = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--exact" => filter.exact = true,
+                "--ignored" => filter.ignored = true,
+                "--include-ignored" => filter.include_ignored = true,
+                _ => {}
+            }
+
+            if let Some(skip) = arg.strip_prefix("--skip=") {
+                filter.skip.push(skip.to_string());
+                continue;
+            }
+
+            if VALUE_OPTIONS.contains(&arg.as_str()) {
+                let value = args.next();
+                if arg == "--skip" {
+                    filter.skip.extend(value);
+                }
+                continue;
+            }
+
+            if arg.starts_with('-') {
+                continue;
+            }
+
+            filter.filters.push(arg);
+        }
+
+        filter
+    }
+
+    /// Returns `true` if tests with the given `ignore` status should run.
+    pub fn includes(&self, ignored: bool) -> bool {
+        self.include_ignored || self.ignored == ignored
+    }
+
+    /// Returns `true` if `--include-ignored` was passed.
+    pub fn include_ignored(&self) -> bool {
+        self.include_ignored
+    }
+
+    /// Returns `true` if the name of the test matches the filters.
+    pub fn matches(&self, name: &str) -> bool {
+        let matches = |filter: &String| {
+            if self.exact {
+                name == filter
+            } else {
+                name.contains(filter.as_str())
+            }
+        };
+
+        (self.filters.is_empty() || self.filters.iter().any(matches))
+            && !self.skip.iter().any(matches)
+    }
+}
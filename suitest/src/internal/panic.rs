@@ -0,0 +1,167 @@
+use crate::SuiteOutcome;
+use futures_util::FutureExt;
+use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+
+/// The panic payload of a test whose setup hooks failed, marking the test as errored
+/// rather than failed. Wraps the original payload.
+pub struct SetupFailed(pub Box<dyn Any + Send>);
+
+/// Fail the current test or hook with the given message.
+///
+/// Prints the message and unwinds with it as the payload. Unwinding instead of panicking
+/// keeps the panic hook from printing the location of the generated code.
+pub fn fail(msg: String) -> ! {
+    eprintln!("{msg}");
+    std::panic::resume_unwind(Box::new(msg))
+}
+
+/// Obtain the message from a panic payload, if it is a string.
+pub fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+/// Unwraps the result of a test or hook, failing with the error's debug output if it is an error.
+pub fn unwrap_result<T, E: std::fmt::Debug>(result: Result<T, E>, fn_id: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => fail(format!("{fn_id} returned an error: {e:?}")),
+    }
+}
+
+/// Checks the result of a test annotated with `should_panic`. Fails if the test did
+/// not panic or if the panic message does not contain the expected string.
+pub fn expect_panic(result: std::thread::Result<()>, expected: Option<&str>, name: &str) {
+    let payload = match result {
+        Ok(()) => fail(format!("{name} did not panic as expected")),
+        Err(payload) => payload,
+    };
+
+    let Some(expected) = expected else {
+        return;
+    };
+
+    match panic_message(&*payload) {
+        Some(msg) if msg.contains(expected) => {}
+        Some(msg) => fail(format!(
+            "{name} panicked, but the panic message did not contain the expected string\n      panic message: {msg:?}\n expected substring: {expected:?}"
+        )),
+        None => fail(format!(
+            "{name} panicked with a non-string payload\n expected substring: {expected:?}"
+        )),
+    }
+}
+
+/// Run `attempt` until it succeeds or the retries are exhausted, invoking `reset` with the panic
+/// after each failed attempt. Returns the number of attempts it took for the test to pass.
+/// Unwinds with the last panic if all attempts fail.
+pub fn retry(
+    name: &str,
+    retries: usize,
+    mut attempt: impl FnMut(),
+    mut reset: impl FnMut(&(dyn Any + Send)),
+) -> usize {
+    let mut attempts = 1;
+    loop {
+        match std::panic::catch_unwind(AssertUnwindSafe(&mut attempt)) {
+            Ok(()) => return attempts,
+            Err(e) if attempts <= retries => {
+                eprintln!("{name} failed on attempt {attempts}, retrying");
+                reset(&*e);
+                attempts += 1;
+            }
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+
+/// Same as [retry], but for async tests.
+pub async fn retry_async<A, AF, R, RF>(
+    name: &str,
+    retries: usize,
+    mut attempt: A,
+    mut reset: R,
+) -> usize
+where
+    A: FnMut() -> AF,
+    AF: Future<Output = ()>,
+    R: FnMut(&(dyn Any + Send)) -> RF,
+    RF: Future<Output = ()>,
+{
+    let mut attempts = 1;
+    loop {
+        match AssertUnwindSafe(attempt()).catch_unwind().await {
+            Ok(()) => return attempts,
+            Err(e) if attempts <= retries => {
+                eprintln!("{name} failed on attempt {attempts}, retrying");
+                reset(&*e).await;
+                attempts += 1;
+            }
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+
+/// Run the `before_each` hooks of a test, marking their panic as a failed setup.
+pub fn setup(name: &str, hooks: impl FnOnce()) {
+    if let Err(e) = std::panic::catch_unwind(AssertUnwindSafe(hooks)) {
+        eprintln!("setup failed in before_each for test {name}");
+        std::panic::resume_unwind(Box::new(SetupFailed(e)))
+    }
+}
+
+/// Same as [setup], but for async tests.
+pub async fn setup_async(name: &str, hooks: impl Future<Output = ()>) {
+    if let Err(e) = AssertUnwindSafe(hooks).catch_unwind().await {
+        eprintln!("setup failed in before_each for test {name}");
+        std::panic::resume_unwind(Box::new(SetupFailed(e)))
+    }
+}
+
+/// Whether the panic comes from the setup hooks of a test.
+pub fn is_setup_failure(payload: &(dyn Any + Send)) -> bool {
+    payload.is::<SetupFailed>()
+}
+
+/// Obtain the original panic payload of a failed setup.
+pub fn into_panic(payload: Box<dyn Any + Send>) -> Box<dyn Any + Send> {
+    match payload.downcast::<SetupFailed>() {
+        Ok(setup) => setup.0,
+        Err(payload) => payload,
+    }
+}
+
+/// Build the outcome of a suite from the panics of its tests and the tests that did not
+/// run because `before_all` failed.
+pub fn outcome(
+    errors: &[(&'static str, Box<dyn Any + Send>)],
+    mut errored: Vec<&'static str>,
+    skipped: Vec<&'static str>,
+) -> SuiteOutcome {
+    let mut failed = vec![];
+    for (name, e) in errors {
+        if is_setup_failure(&**e) {
+            errored.push(name);
+        } else {
+            failed.push(*name);
+        }
+    }
+    SuiteOutcome::new(failed, errored, skipped)
+}
+
+/// The results of a nested suite, merged into the results of the suite it is declared in.
+pub struct ChildResults {
+    /// The panics of the failed tests along with their names.
+    pub errors: Vec<(&'static str, Box<dyn Any + Send>)>,
+
+    /// The tests that did not run because `before_all` failed.
+    pub errored: Vec<&'static str>,
+
+    /// The tests that did not run because of `fail_fast`.
+    pub skipped: Vec<&'static str>,
+
+    /// The panic of the suite's `before_all` or `after_all` hooks, if any.
+    pub panic: Option<Box<dyn Any + Send>>,
+}
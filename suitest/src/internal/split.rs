@@ -0,0 +1,137 @@
+use super::{
+    filter::TestFilter,
+    panic::{is_setup_failure, SetupFailed},
+};
+use crate::SuiteOutcome;
+use once_cell::sync::OnceCell;
+use std::{
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex, MutexGuard, Once, PoisonError,
+    },
+    thread,
+};
+
+/// Coordinates the suite hooks when each test in the suite is registered as a
+/// separate libtest test.
+///
+/// The first test to run executes `before_all` while the others wait for it to complete.
+/// The last test to finish is responsible for `after_all`, `teardown` and dropping the global state.
+pub struct SplitSuite {
+    /// The `module_path!()` of the suite module, used to obtain libtest test names.
+    module: &'static str,
+
+    /// The identifiers of all the tests in the suite and whether they are ignored.
+    tests: &'static [(&'static str, bool)],
+
+    /// Guards the `before_all` hook.
+    setup: Once,
+
+    /// Set if the `before_all` hook panicked.
+    setup_failed: AtomicBool,
+
+    /// The number of tests selected to run in this process that have not yet finished.
+    /// Initialised by the first test to finish.
+    remaining: OnceCell<AtomicUsize>,
+
+    /// The names of the failed tests in the suite.
+    failed: Mutex<Vec<&'static str>>,
+
+    /// The names of the tests that did not run because `before_all` or their `before_each` hooks failed.
+    errored: Mutex<Vec<&'static str>>,
+
+    /// Used to run the tests one by one in sequential suites.
+    lock: Mutex<()>,
+}
+
+impl SplitSuite {
+    pub const fn new(module: &'static str, tests: &'static [(&'static str, bool)]) -> Self {
+        Self {
+            module,
+            tests,
+            setup: Once::new(),
+            setup_failed: AtomicBool::new(false),
+            remaining: OnceCell::new(),
+            failed: Mutex::new(vec![]),
+            errored: Mutex::new(vec![]),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Run the `before_all` hook if no other test in the suite has run it yet.
+    /// Blocks until the hook completes.
+    ///
+    /// Returns an error marking the test as errored if the hook panicked.
+    pub fn setup(&self, before_all: impl FnOnce()) -> thread::Result<()> {
+        self.setup.call_once(|| {
+            if std::panic::catch_unwind(AssertUnwindSafe(before_all)).is_err() {
+                self.setup_failed.store(true, Ordering::SeqCst);
+            }
+        });
+
+        if self.setup_failed.load(Ordering::SeqCst) {
+            let msg = "setup failed in before_all";
+            eprintln!("{msg}");
+            return Err(Box::new(SetupFailed(Box::new(msg))));
+        }
+        Ok(())
+    }
+
+    /// Obtain the lock used for running tests sequentially.
+    pub fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Mark a test as finished.
+    ///
+    /// Returns `Some` for the last test to finish, containing the outcome of the suite.
+    pub fn finish(&self, name: &'static str, result: &thread::Result<()>) -> Option<SuiteOutcome> {
+        match result {
+            Ok(()) => {}
+            Err(e) if is_setup_failure(&**e) => self
+                .errored
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(name),
+            Err(_) => self
+                .failed
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(name),
+        }
+
+        let remaining = self
+            .remaining
+            .get_or_init(|| AtomicUsize::new(self.selected().max(1)));
+
+        let previous = remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .ok()?;
+
+        (previous == 1).then(|| {
+            let failed = self.failed.lock().unwrap_or_else(PoisonError::into_inner);
+            let errored = self.errored.lock().unwrap_or_else(PoisonError::into_inner);
+            SuiteOutcome::new(failed.clone(), errored.clone(), vec![])
+        })
+    }
+
+    /// The number of tests in the suite libtest will run in this process.
+    fn selected(&self) -> usize {
+        let filter = TestFilter::from_env();
+        self.tests
+            .iter()
+            .filter(|(test, ignored)| {
+                filter.includes(*ignored) && filter.matches(&self.test_name(test))
+            })
+            .count()
+    }
+
+    /// The name libtest uses for the test, i.e. its path without the crate name.
+    fn test_name(&self, test: &str) -> String {
+        match self.module.split_once("::") {
+            Some((_, path)) => format!("{path}::{test}"),
+            None => test.to_string(),
+        }
+    }
+}
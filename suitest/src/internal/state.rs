@@ -0,0 +1,188 @@
+use std::{
+    any::{Any, TypeId},
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, PoisonError, RwLock},
+};
+use tokio::{
+    runtime::RuntimeFlavor,
+    sync::{Mutex, OwnedMutexGuard},
+};
+
+type StateValue = Arc<dyn Any + Send + Sync>;
+
+/// Items are identified by their type and an optional name, so multiple items of
+/// the same type can be stored under different names.
+type StateKey = (TypeId, Option<&'static str>);
+
+/// Holds the items returned from the suite hooks, one per type and name.
+///
+/// Items are written by the hooks preceding a phase of the suite and shared with
+/// everything that runs afterwards. Reads hand out a reference counted handle to the
+/// item, so clearing the map never invalidates an item still in use; the item is
+/// dropped once the last handle to it is gone.
+pub struct StateMap {
+    items: RwLock<BTreeMap<StateKey, StateValue>>,
+}
+
+impl StateMap {
+    pub const fn new() -> Self {
+        Self {
+            items: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Insert the item to the state, replacing the existing unnamed item of the same type.
+    pub fn insert<T: Any + Send + Sync>(&self, item: T) {
+        self.insert_named(None, item)
+    }
+
+    /// Insert the item to the state under the given name, replacing the existing item
+    /// of the same type and name.
+    pub fn insert_named<T: Any + Send + Sync>(&self, name: Option<&'static str>, item: T) {
+        let replaced = self
+            .items
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert((TypeId::of::<T>(), name), Arc::new(item));
+        // Dropped outside of the lock in case the drop implementation panics
+        drop(replaced);
+    }
+
+    /// Obtain a handle to the unnamed item of type `T`, if it is in the state.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.get_named(None)
+    }
+
+    /// Obtain a handle to the item of type `T` with the given name, if it is in the state.
+    pub fn get_named<T: Any + Send + Sync>(&self, name: Option<&'static str>) -> Option<Arc<T>> {
+        let item = self
+            .items
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&(TypeId::of::<T>(), name))
+            .cloned()?;
+        Some(
+            item.downcast()
+                .unwrap_or_else(|_| unreachable!("items are keyed by their type")),
+        )
+    }
+
+    /// Obtain exclusive access to the item of type `T` with the given name, if it is in the state.
+    /// The item is removed from the state until the returned guard is dropped.
+    ///
+    /// Panics if the item is in use.
+    pub fn get_mut<T: Any + Send + Sync>(
+        &self,
+        name: Option<&'static str>,
+    ) -> Option<StateMut<'_, T>> {
+        let item = self.remove(name, "obtain mutable access to")?;
+        Some(StateMut {
+            state: self,
+            name,
+            item: Some(item),
+        })
+    }
+
+    /// Move the item of type `T` with the given name out of the state, if it is in the state.
+    ///
+    /// Panics if the item is in use.
+    pub fn take<T: Any + Send + Sync>(&self, name: Option<&'static str>) -> Option<T> {
+        self.remove(name, "take")
+    }
+
+    /// Remove the item from the state. If there are other handles to the item it is put
+    /// back and the removal panics with a message saying what was attempted.
+    fn remove<T: Any + Send + Sync>(&self, name: Option<&'static str>, action: &str) -> Option<T> {
+        let key = (TypeId::of::<T>(), name);
+        let item = self
+            .items
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&key)?
+            .downcast::<T>()
+            .unwrap_or_else(|_| unreachable!("items are keyed by their type"));
+
+        match Arc::try_unwrap(item) {
+            Ok(item) => Some(item),
+            Err(item) => {
+                self.items
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(key, item);
+                panic!(
+                    "cannot {action} '{}' while it is in use",
+                    std::any::type_name::<T>()
+                )
+            }
+        }
+    }
+
+    /// Remove all the items from the state, dropping the ones not in use.
+    pub fn clear(&self) {
+        let items =
+            std::mem::take(&mut *self.items.write().unwrap_or_else(PoisonError::into_inner));
+        drop(items);
+    }
+}
+
+impl Default for StateMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Exclusive access to an item of a [StateMap]. The item is put back to the state
+/// when the guard is dropped.
+pub struct StateMut<'a, T: Any + Send + Sync> {
+    state: &'a StateMap,
+    name: Option<&'static str>,
+    item: Option<T>,
+}
+
+impl<T: Any + Send + Sync> Deref for StateMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.item.as_ref().expect("item taken")
+    }
+}
+
+impl<T: Any + Send + Sync> DerefMut for StateMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item.as_mut().expect("item taken")
+    }
+}
+
+impl<T: Any + Send + Sync> Drop for StateMut<'_, T> {
+    fn drop(&mut self) {
+        if let Some(item) = self.item.take() {
+            self.state.insert_named(self.name, item);
+        }
+    }
+}
+
+/// The lock global items requested with `#[shared]` are stored in.
+pub type Shared<T> = Mutex<T>;
+
+/// Lock the shared item from a sync hook or test.
+///
+/// Sync functions called from async tasks run in the context of the runtime, where
+/// blocking on the lock directly is not permitted. On the multi threaded runtime the
+/// worker is handed over to the other tasks while this one blocks. The current thread
+/// runtime cannot run any other task while this one is blocked, so waiting on a lock
+/// held by another task would never finish and fails instead.
+pub fn lock_blocking<T: Send + 'static>(shared: Arc<Shared<T>>) -> OwnedMutexGuard<T> {
+    match tokio::runtime::Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Err(_) => shared.blocking_lock_owned(),
+        Ok(RuntimeFlavor::MultiThread) => {
+            tokio::task::block_in_place(|| shared.blocking_lock_owned())
+        }
+        Ok(_) => shared.try_lock_owned().unwrap_or_else(|_| {
+            panic!(
+                "cannot wait for the shared item '{}' in a sync function on the current_thread runtime, make the function async",
+                std::any::type_name::<T>()
+            )
+        }),
+    }
+}
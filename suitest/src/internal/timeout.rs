@@ -0,0 +1,82 @@
+use super::panic::fail;
+use std::{
+    future::Future,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Handle to a thread spawned with [spawn_timed].
+pub struct TimedHandle<T> {
+    /// Used to report the timeout.
+    name: String,
+
+    handle: JoinHandle<T>,
+
+    /// Disconnects once the thread finishes, regardless of whether it panicked.
+    done: Receiver<()>,
+
+    /// The instant after which the thread is considered timed out and its timeout.
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl<T> TimedHandle<T> {
+    /// Join the thread. If the thread does not finish before its deadline, it is left
+    /// running and an error containing the timeout message is returned.
+    pub fn join(self) -> thread::Result<T> {
+        if let Some((deadline, timeout)) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = self.done.recv_timeout(remaining) {
+                let msg = format!("{} timed out after {timeout:?}", self.name);
+                eprintln!("{msg}");
+                return Err(Box::new(msg));
+            }
+        }
+        self.handle.join()
+    }
+}
+
+/// Spawn the task on a thread named `name` which times out after `timeout`, if given.
+/// The deadline starts counting as soon as the thread is spawned.
+pub fn spawn_timed<T, F>(name: &str, timeout: Option<Duration>, task: F) -> TimedHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, done) = mpsc::channel::<()>();
+
+    let handle = thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            // Dropped when the task finishes or panics
+            let _tx = tx;
+            task()
+        })
+        .expect("could not spawn test thread");
+
+    TimedHandle {
+        name: name.to_string(),
+        handle,
+        done,
+        deadline: timeout.map(|timeout| (Instant::now() + timeout, timeout)),
+    }
+}
+
+/// Run the task on a separate thread, failing if it does not finish within `timeout`.
+pub fn run_timed<T, F>(name: &str, timeout: Duration, task: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_timed(name, Some(timeout), task)
+        .join()
+        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+}
+
+/// Await the future, failing if it does not complete within `duration`.
+pub async fn timeout<F: Future>(duration: Duration, name: &str, future: F) -> F::Output {
+    match tokio::time::timeout(duration, future).await {
+        Ok(output) => output,
+        Err(_) => fail(format!("{name} timed out after {duration:?}")),
+    }
+}
@@ -1,10 +1,12 @@
 #![doc = include_str!(concat!("../", std::env!("CARGO_PKG_README")))]
+#![allow(clippy::test_attr_in_doctest)]
 
+pub use context::TestContext;
+pub use outcome::SuiteOutcome;
 pub use suitest_macros::*;
 
+mod context;
+mod outcome;
+
 #[doc(hidden)]
-pub mod internal {
-    pub use futures_util;
-    pub use once_cell;
-    pub use once_cell::sync::OnceCell;
-}
+pub mod internal;
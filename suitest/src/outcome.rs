@@ -0,0 +1,43 @@
+/// The result of running the tests in a suite, passed to the `teardown` hooks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SuiteOutcome {
+    failed: Vec<&'static str>,
+    errored: Vec<&'static str>,
+    skipped: Vec<&'static str>,
+}
+
+impl SuiteOutcome {
+    #[doc(hidden)]
+    pub fn new(
+        failed: Vec<&'static str>,
+        errored: Vec<&'static str>,
+        skipped: Vec<&'static str>,
+    ) -> Self {
+        Self {
+            failed,
+            errored,
+            skipped,
+        }
+    }
+
+    /// Whether all the tests passed, i.e. none of them failed or errored.
+    pub fn passed(&self) -> bool {
+        self.failed.is_empty() && self.errored.is_empty()
+    }
+
+    /// The names of the failed tests, in the order they were reported.
+    pub fn failed(&self) -> &[&'static str] {
+        &self.failed
+    }
+
+    /// The names of the tests that did not run because `before_all` or their
+    /// `before_each` hooks failed.
+    pub fn errored(&self) -> &[&'static str] {
+        &self.errored
+    }
+
+    /// The names of the tests skipped after the first failure when `fail_fast` is set.
+    pub fn skipped(&self) -> &[&'static str] {
+        &self.skipped
+    }
+}
@@ -1,15 +1,108 @@
 use crate::suite::{
-    FnQuote, PathOrTupleExpr, PathOrTupleReturn, StateModifier, SuiteConfig, SuiteFn, TaskQuote,
-    TestFn, TestSuite,
+    is_attr, quote_duration, type_display, Access, ChildSuite, Fixture, FnQuote, HookOutput,
+    InheritedState, ShouldPanic, StateArg, StateModifier, SuiteConfig, SuiteFn, SuiteRuntime,
+    TaskQuote, TestCase, TestFn, TestSuite, TypeCheck, UseFixtures, ANNOTATIONS, TEST,
 };
-use proc_macro_error::abort;
-use quote::{format_ident, quote};
+use proc_macro_error::{abort, emit_error};
+use quote::{format_ident, quote, quote_spanned};
 use syn::{
-    punctuated::Punctuated, spanned::Spanned, token::Comma, Expr, FnArg, Ident, ItemFn, ItemMod,
-    Pat, Signature, Type,
+    punctuated::Punctuated, spanned::Spanned, token::Comma, FnArg, Ident, Item, ItemFn, ItemMod,
+    ItemUse, LitStr, Pat, PatType, ReturnType, Signature, Type, UseGroup, UsePath, UseTree,
 };
 
-pub fn impl_suite(id: Ident, item_mod: ItemMod) -> proc_macro2::TokenStream {
+/// Generates the suite once the hooks of all the `fixtures` modules it uses are collected.
+///
+/// Since a macro cannot read the items of another module, each `fixtures` module declares a
+/// `__suitest_fixtures` macro passing its hooks back to `__fixtures` along with the suite.
+/// The suite is handed from one `fixtures` module to the next until none are left.
+pub fn impl_use_fixtures(
+    UseFixtures {
+        id,
+        pending,
+        fixtures,
+        item_mod,
+    }: UseFixtures,
+) -> proc_macro2::TokenStream {
+    let Some(next) = pending.first() else {
+        return impl_suite(id, item_mod, fixtures);
+    };
+    quote!(
+        #next::__suitest_fixtures! {
+            #id [#(#pending),*] [#(#fixtures)*] #item_mod
+        }
+    )
+}
+
+/// Removes the hooks from the `fixtures` module and declares the macro passing them to the suites using it.
+pub fn impl_fixtures(item_mod: ItemMod) -> proc_macro2::TokenStream {
+    let ItemMod {
+        attrs,
+        vis,
+        mod_token,
+        ident,
+        content,
+        ..
+    } = item_mod;
+
+    let Some((_, items)) = content else {
+        abort!(ident.span(), "fixtures must be declared inline")
+    };
+
+    let mut imports = vec![];
+    let mut hooks = vec![];
+    let mut other = vec![];
+
+    for item in items {
+        match item {
+            Item::Fn(item)
+                if item
+                    .attrs
+                    .iter()
+                    .any(|attr| ANNOTATIONS.iter().any(|name| is_attr(attr, name))) =>
+            {
+                if let Some(attr) = item.attrs.iter().find(|attr| is_attr(attr, TEST)) {
+                    abort!(
+                        attr.span(),
+                        "fixtures can only declare hooks";
+                        help = "move the test to the suites using the fixtures"
+                    )
+                }
+                hooks.push(item);
+            }
+            // The hooks are generated in the suites, along with the imports they might need
+            Item::Use(item) => {
+                imports.push(item.clone());
+                other.push(quote!(#[allow(unused_imports)] #item));
+            }
+            item => other.push(quote!(#item)),
+        }
+    }
+
+    quote!(
+        #(#attrs)*
+        #vis #mod_token #ident {
+            #(#other)*
+
+            #[doc(hidden)]
+            macro_rules! __suitest_fixtures {
+                ($($suite:tt)*) => {
+                    suitest::__fixtures! {
+                        { #(#imports)* #(#hooks)* } $($suite)*
+                    }
+                };
+            }
+
+            #[doc(hidden)]
+            pub(crate) use __suitest_fixtures;
+        }
+    )
+}
+
+pub fn impl_suite(
+    id: Ident,
+    item_mod: ItemMod,
+    fixtures: Vec<Fixture>,
+) -> proc_macro2::TokenStream {
     // Skip empty modules
     if item_mod.content.is_none()
         || item_mod
@@ -27,13 +120,29 @@ pub fn impl_suite(id: Ident, item_mod: ItemMod) -> proc_macro2::TokenStream {
 
     let mut suite = TestSuite::new(id, config);
 
-    // Identifiers for local test states
+    // Identifiers for local test states, shared with the nested suites
     let mut i = 0;
 
+    // The hooks of the fixtures come before the ones of the suite
+    for fixture in fixtures {
+        suite.process_fixture(&mut i, fixture);
+    }
+
     for item in items {
         suite.process_item(&mut i, item)
     }
 
+    suite.verify_fixtures();
+    suite.sort_hooks();
+    suite.verify_state(&InheritedState::default());
+
+    if let Some(child) = suite.children.first().filter(|_| suite.config.split) {
+        abort!(
+            child.ident.span(),
+            "nested suites cannot be declared in `split` suites"
+        )
+    }
+
     let ItemMod {
         attrs,
         vis,
@@ -42,6 +151,103 @@ pub fn impl_suite(id: Ident, item_mod: ItemMod) -> proc_macro2::TokenStream {
         ..
     } = item_mod;
 
+    let root = SuiteRoot {
+        len: i,
+        track: suite.tracks(),
+        is_async: suite.is_async,
+    };
+
+    let cases = suite.cases();
+
+    let len = root.len;
+    let maps = {
+        let mut v = vec![];
+        for _ in 0..len {
+            v.push(quote!(suitest::internal::StateMap::new(),));
+        }
+        v
+    };
+    let local_map = quote!(static __LOCAL: [suitest::internal::StateMap; #len] = [#(#maps)*];);
+
+    let context_map = root.track.then(|| {
+        let mut names = cases
+            .iter()
+            .map(|(id, name, _)| (*id, name))
+            .collect::<Vec<_>>();
+        names.sort_by_key(|(id, _)| *id);
+        let trackers = names
+            .iter()
+            .map(|(_, name)| quote!(suitest::internal::TestTracker::new(#name)));
+        quote!(static __CONTEXT: [suitest::internal::TestTracker; #len] = [#(#trackers),*];)
+    });
+
+    let content = quote_suite(suite, &root, &Inherited::default());
+
+    quote!(
+        #(#attrs)*
+        #vis #mod_token #ident {
+            #local_map
+
+            #context_map
+
+            #content
+        }
+    )
+}
+
+/// What is shared by all the suites nested in the outermost one.
+struct SuiteRoot {
+    /// The number of local states, one for each test case in all the suites
+    len: usize,
+
+    /// Whether the attempts of the tests are tracked for the `TestContext`
+    track: bool,
+
+    /// Whether a tokio runtime is needed by any of the suites
+    is_async: bool,
+}
+
+/// The hooks of the enclosing suites running for each test of a nested suite, outermost first.
+#[derive(Clone, Default)]
+struct Inherited {
+    before_each: Vec<FnQuote>,
+    after_each: Vec<FnQuote>,
+    around_each: Vec<FnQuote>,
+    cleanup: Vec<FnQuote>,
+}
+
+impl Inherited {
+    /// The hooks invoked from a suite nested in the one with the given hooks.
+    fn extend(&self, own: &Inherited) -> Self {
+        let child = |inherited: &[FnQuote], own: &[FnQuote]| {
+            inherited.iter().chain(own).map(FnQuote::in_child).collect()
+        };
+        Self {
+            before_each: child(&self.before_each, &own.before_each),
+            after_each: child(&self.after_each, &own.after_each),
+            around_each: child(&self.around_each, &own.around_each),
+            cleanup: child(&self.cleanup, &own.cleanup),
+        }
+    }
+}
+
+/// Generates the contents of the suite module, along with the modules of its nested suites.
+///
+/// The outermost suite is run by a libtest test, while nested suites are run by a `__suite` fn
+/// invoked by the suite they are declared in once its tests finish.
+fn quote_suite(
+    suite: TestSuite,
+    root: &SuiteRoot,
+    inherited: &Inherited,
+) -> proc_macro2::TokenStream {
+    // The names of all the tests, including the ones of nested suites, and whether they are ignored
+    let subtree = suite.cases();
+    let has_ignored = subtree.iter().any(|(_, _, ignored)| *ignored);
+    let test_names = subtree
+        .into_iter()
+        .map(|(_, name, ignored)| quote!((#name, #ignored)))
+        .collect::<Vec<_>>();
+
     let TestSuite {
         suite_id,
         config,
@@ -50,122 +256,388 @@ pub fn impl_suite(id: Ident, item_mod: ItemMod) -> proc_macro2::TokenStream {
         before_each,
         after_all,
         after_each,
+        around_each,
         cleanup,
-        is_async,
+        teardown,
         other_items: other,
+        children,
+        depth,
+        prefix,
+        fixtures,
+        ..
     } = suite;
 
+    let is_child = depth > 0;
+    let is_async = root.is_async;
+    let track = root.track;
+
     // If any of the fns are async a tokio runtime needs to be spawned
+    let runtime_builder = config.quote_runtime();
     let runtime = is_async.then_some(quote!(
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("couldn't build runtime");
+        let rt = #runtime_builder;
     ));
 
+    // Each case of a test runs with its own local state
+    let cases = tests
+        .iter()
+        .flat_map(|test| test.cases.iter().map(move |case| (test, case)))
+        .collect::<Vec<_>>();
+
     // Used as identifiers for the tests and their hooks
-    let ids = tests
+    let ids = cases
         .iter()
-        .map(|t| format_ident!("ID{}", t.id))
+        .map(|(_, case)| format_ident!("ID{}", case.id))
         .collect::<Vec<_>>();
 
     // Used as actual usize literals for the const generics
-    let id_lits = tests.iter().map(|t| t.id).collect::<Vec<_>>();
-
-    let len = tests.len();
-
-    let maps = {
-        let mut v = vec![];
-        for _ in 0..len {
-            v.push(quote!(suitest::internal::once_cell::sync::Lazy::new(
-                ::std::collections::HashMap::new
-            ),));
-        }
-        v
-    };
-    let local_map = quote!(static mut __LOCAL: [suitest::internal::once_cell::sync::Lazy<__AnyMap>; #len] = [#(#maps)*];);
+    let id_lits = cases.iter().map(|(_, case)| case.id).collect::<Vec<_>>();
 
     let verbose = config.verbose;
 
-    let before_all = quote_suite_fn(
-        before_all.as_ref(),
-        false,
-        verbose.then_some("Running before_all"),
-    );
+    let quote_hooks = |hooks: &[SuiteFn], local: bool, msg: &str| {
+        hooks
+            .iter()
+            .map(|hook| {
+                let fixture = hook.fixture.map(|i| &fixtures[i]);
+                quote_suite_fn(hook, fixture, local, depth, verbose.then_some(msg))
+            })
+            .collect::<Vec<_>>()
+    };
 
-    let after_all = quote_suite_fn(
-        after_all.as_ref(),
-        false,
-        verbose.then_some("Running after_all"),
-    );
+    let before_all = quote_hooks(&before_all, false, "Running before_all");
+    let after_all = quote_hooks(&after_all, false, "Running after_all");
+    let before_each = quote_hooks(&before_each, true, "Running before_each");
+    let after_each = quote_hooks(&after_each, true, "Running after_each");
+    let around_each = quote_hooks(&around_each, true, "Running around_each");
+    let cleanup = quote_hooks(&cleanup, true, "Running cleanup");
+    let teardown = quote_hooks(&teardown, false, "Running teardown");
 
-    let before_each = quote_suite_fn(
-        before_each.as_ref(),
-        true,
-        verbose.then_some("Running before_each"),
-    );
+    let own = Inherited {
+        before_each,
+        after_each,
+        around_each,
+        cleanup,
+    };
 
-    let after_each = quote_suite_fn(
-        after_each.as_ref(),
-        true,
-        verbose.then_some("Running after_each"),
-    );
+    // The tests of nested suites also run the hooks of the suites they are declared in
+    let hooks = Inherited {
+        before_each: [inherited.before_each.as_slice(), &own.before_each].concat(),
+        after_each: [inherited.after_each.as_slice(), &own.after_each].concat(),
+        around_each: [inherited.around_each.as_slice(), &own.around_each].concat(),
+        cleanup: [inherited.cleanup.as_slice(), &own.cleanup].concat(),
+    };
 
-    let cleanup = quote_suite_fn(cleanup.as_ref(), true, verbose.then_some("Running cleanup"));
+    // `after_*` hooks run in the reverse order of their `before_*` counterparts
+    let ba_invoke = before_all.iter().map(FnQuote::quote_invoke_suite);
+    let ba_invoke = quote!(#(#ba_invoke)*);
+    let aa_invoke = after_all.iter().rev().map(FnQuote::quote_invoke_suite);
+    let aa_invoke = quote!(#(#aa_invoke)*);
+    // Each `teardown` runs even if the ones before it panicked, keeping the first panic
+    let td_invoke = teardown.iter().rev().map(|fq| {
+        let invoke = fq.quote_invoke_suite();
+        quote!(
+            let after_all = after_all.and(
+                ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| { #invoke }))
+            );
+        )
+    });
+    let td_invoke = quote!(#(#td_invoke)*);
 
-    let ba_invoke = before_all.as_ref().map(FnQuote::quote_invoke_suite);
-    let aa_invoke = after_all.as_ref().map(FnQuote::quote_invoke_suite);
+    // Runs the `after_all` hooks if the suite passed, followed by `teardown` which runs
+    // even if `after_all` panics. The first panic is resumed once the global state is dropped.
+    // Nested suites return the panic to the suite they are declared in instead.
+    let (aa_invoke, aa_result) = if teardown.is_empty() && !is_child {
+        (quote!(if outcome.passed() { #aa_invoke }), None)
+    } else {
+        (
+            quote!(
+                let after_all = if outcome.passed() {
+                    ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| { #aa_invoke }))
+                } else {
+                    ::std::result::Result::Ok(())
+                };
+                #td_invoke
+            ),
+            Some(quote!(if let Err(e) = after_all {
+                ::std::panic::resume_unwind(e);
+            })),
+        )
+    };
 
-    let test_declarations = quote_test_declarations(&tests, verbose);
+    let test_declarations = quote_test_declarations(&tests, depth, verbose);
 
     let mut test_tasks = quote!();
+    let mut blocking_reported = vec![];
     let mut tasks_sync = vec![];
     let mut tasks_async = vec![];
+    let mut split_tests = vec![quote!(); tests.len()];
 
-    for (test, const_id) in tests.iter().zip(ids.iter()) {
-        let TestFn { id, item, .. } = test;
+    for ((test_idx, test), case) in tests
+        .iter()
+        .enumerate()
+        .flat_map(|(i, test)| test.cases.iter().map(move |case| ((i, test), case)))
+    {
+        let TestFn { item, .. } = test;
+        let TestCase { id, args, .. } = case;
+        let const_id = &format_ident!("ID{id}");
 
-        let test_id = &item.sig.ident;
+        let test_id = test.impl_id();
+        let name = format!("{prefix}{}", case.name(&item.sig.ident));
         let is_async = item.sig.asyncness.is_some();
 
-        let be_invoke = before_each
-            .as_ref()
+        let be_invoke = hooks
+            .before_each
+            .iter()
             .map(|fq| fq.quote_invoke_task(const_id));
+        let be_invoke = quote!(#(#be_invoke)*);
 
-        let ae_invoke = after_each.as_ref().map(|fq| fq.quote_invoke_task(const_id));
+        let ae_invoke = hooks
+            .after_each
+            .iter()
+            .rev()
+            .map(|fq| fq.quote_invoke_task(const_id));
+        let ae_invoke = quote!(#(#ae_invoke)*);
 
         let test_invoke = if is_async {
-            quote!(#test_id::<#const_id>().await;)
+            quote!(#test_id::<#const_id>(#args).await)
         } else {
-            quote!(#test_id::<#const_id>();)
+            quote!(#test_id::<#const_id>(#args))
         };
 
+        let test_invoke = if matches!(item.sig.output, ReturnType::Default) {
+            quote!(#test_invoke;)
+        } else {
+            quote!(suitest::internal::unwrap_result(#test_invoke, #name);)
+        };
+
+        // Only panics from the test itself count towards `should_panic`
+        let test_invoke = match test.should_panic {
+            Some(ShouldPanic { ref expected }) => {
+                let expected = match expected {
+                    Some(expected) => quote!(::std::option::Option::Some(#expected)),
+                    None => quote!(::std::option::Option::None),
+                };
+                let result = if is_async {
+                    quote!(
+                        suitest::internal::futures_util::FutureExt::catch_unwind(
+                            ::std::panic::AssertUnwindSafe(async { #test_invoke })
+                        ).await
+                    )
+                } else {
+                    quote!(::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| { #test_invoke })))
+                };
+                quote!(suitest::internal::expect_panic(#result, #expected, #name);)
+            }
+            None => test_invoke,
+        };
+
+        // `around_each` hooks wrap the test with the first one in order being the outermost.
+        // Each hook is checked to have run the test, which would otherwise pass without running
+        let mut is_wrapped_async = is_async;
+        let mut test_invoke = test_invoke;
+        for fq in hooks.around_each.iter().rev() {
+            let (around_id, around_path) = (&fq.id, fq.quote_path());
+            let not_run = format!("around_each hook `{around_id}` did not run the test");
+            let invoke = if fq.is_async {
+                quote!(#around_path::<#const_id>(async move {
+                    #test_invoke
+                    ran.store(true, ::std::sync::atomic::Ordering::SeqCst);
+                }).await;)
+            } else {
+                if is_wrapped_async {
+                    emit_error!(
+                        item.sig.ident,
+                        "async test `{}` cannot be wrapped by the sync around_each hook `{}`",
+                        item.sig.ident,
+                        around_id;
+                        help = "make `{}` async", around_id
+                    );
+                }
+                quote!(#around_path::<#const_id>(move || {
+                    #test_invoke
+                    ran.store(true, ::std::sync::atomic::Ordering::SeqCst);
+                });)
+            };
+            test_invoke = quote!({
+                let test_ran = ::std::sync::Arc::new(::std::sync::atomic::AtomicBool::new(false));
+                {
+                    let ran = ::std::sync::Arc::clone(&test_ran);
+                    #invoke
+                }
+                if !test_ran.load(::std::sync::atomic::Ordering::SeqCst) {
+                    suitest::internal::fail(::std::string::String::from(#not_run));
+                }
+            });
+            is_wrapped_async |= fq.is_async;
+        }
+
+        let retries = test.retries.unwrap_or(config.retries);
+
+        // Cleanup runs in the task between attempts
         let is_async = is_async
-            || before_each.as_ref().is_some_and(|f| f.is_async)
-            || after_each.as_ref().is_some_and(|f| f.is_async);
+            || hooks.before_each.iter().any(|f| f.is_async)
+            || hooks.after_each.iter().any(|f| f.is_async)
+            || hooks.around_each.iter().any(|f| f.is_async)
+            || (retries > 0 && hooks.cleanup.iter().any(|f| f.is_async));
+
+        // Sync functions block the only thread of the runtime while they wait for a shared
+        // item, so the task holding it could never release it
+        if is_async && !config.sequential && matches!(config.runtime, SuiteRuntime::CurrentThread) {
+            let test_locks = item.sig.asyncness.is_none() && locks_shared(&test.inputs);
+            let blocking = hooks
+                .before_each
+                .iter()
+                .chain(&hooks.after_each)
+                .chain(&hooks.around_each)
+                .chain(hooks.cleanup.iter().filter(|_| retries > 0))
+                .filter(|fq| !fq.is_async && fq.locks_shared)
+                .map(|fq| &fq.id)
+                .chain(test_locks.then_some(&item.sig.ident));
+            for id in blocking {
+                if blocking_reported.contains(id) {
+                    continue;
+                }
+                emit_error!(
+                    id,
+                    "sync function `{}` cannot lock `#[shared]` items in async tests on the current_thread runtime",
+                    id;
+                    help = "make `{}` async, run the suite with `sequential = true` or use `runtime = \"multi_thread\"`", id
+                );
+                blocking_reported.push(id.clone());
+            }
+        }
 
         let closure = if is_async { quote!(async) } else { quote!(||) };
 
+        // Panics in `before_each` mark the test as errored
+        let be_invoke = match (hooks.before_each.is_empty(), is_async) {
+            (true, _) => be_invoke,
+            (false, true) => {
+                quote!(suitest::internal::setup_async(#name, async { #be_invoke }).await;)
+            }
+            (false, false) => quote!(suitest::internal::setup(#name, || { #be_invoke });),
+        };
+
         let task_id = format_ident!("test{id}");
 
-        let msg = format!("{} ... {}", item.sig.ident, "\u{1F5F8}");
+        // Each attempt is tracked for the `TestContext` of the hooks
+        let start = track.then(|| quote!(__CONTEXT[#const_id].start();));
+        let (payload, fail) = if track {
+            (quote!(e), Some(quote!(__CONTEXT[#const_id].fail(e);)))
+        } else {
+            (quote!(_), None)
+        };
+
+        let msg = format!("{} ... {}", name, "\u{1F5F8}");
         let msg = quote!(println!(#msg));
 
-        let tokens = quote!(
-            let #task_id = #closure {
+        // Each attempt of retried tests gets its own deadline instead of the whole task
+        let timeout = test.timeout.or(config.timeout);
+        let (timeout, attempt_timeout) = match timeout {
+            Some(timeout) if retries > 0 => (None, Some(quote_duration(timeout))),
+            timeout => (timeout, None),
+        };
+
+        let tokens = if retries > 0 {
+            let attempt = quote!(
+                #start
                 #be_invoke
                 #test_invoke
                 #ae_invoke
-                #msg
+            );
+            let cleanup_invoke = hooks
+                .cleanup
+                .iter()
+                .rev()
+                .map(|fq| fq.quote_invoke_task(const_id));
+            let cleanup_invoke = quote!(#(#cleanup_invoke)*);
+            let attempt = match (is_async, attempt_timeout) {
+                (true, Some(timeout)) => {
+                    quote!(suitest::internal::timeout(#timeout, #name, async { #attempt }))
+                }
+                (true, None) => quote!(async { #attempt }),
+                (false, Some(timeout)) => {
+                    quote!(suitest::internal::run_timed(#name, #timeout, || { #attempt }))
+                }
+                (false, None) => quote!({ #attempt }),
             };
-        );
+            let retry = if is_async {
+                quote!(
+                    suitest::internal::retry_async(
+                        #name,
+                        #retries,
+                        || #attempt,
+                        |#payload| {
+                            #fail
+                            async {
+                                #cleanup_invoke
+                                __LOCAL[#const_id].clear();
+                            }
+                        }
+                    ).await
+                )
+            } else {
+                quote!(
+                    suitest::internal::retry(
+                        #name,
+                        #retries,
+                        || #attempt,
+                        |#payload| {
+                            #fail
+                            #cleanup_invoke
+                            __LOCAL[#const_id].clear();
+                        }
+                    )
+                )
+            };
+            let retried_msg = format!("{} ... {} (passed after {{}} attempts)", name, "\u{1F5F8}");
+            quote!(
+                let #task_id = #closure {
+                    let attempts = #retry;
+                    if attempts > 1 {
+                        println!(#retried_msg, attempts);
+                    } else {
+                        #msg
+                    }
+                };
+            )
+        } else {
+            quote!(
+                let #task_id = #closure {
+                    #start
+                    #be_invoke
+                    #test_invoke
+                    #ae_invoke
+                    #msg
+                };
+            )
+        };
 
-        let task = TaskQuote::new(
+        let mut task = TaskQuote::new(
             task_id,
-            item.sig.ident.clone(),
+            name,
             const_id.clone(),
-            cleanup.as_ref().map(|f| (f.id.clone(), f.is_async)),
+            hooks
+                .cleanup
+                .iter()
+                .map(|f| (f.quote_path(), f.is_async))
+                .collect(),
+            test.ignore.is_some(),
+            timeout,
         );
+        task.track = track;
+
+        if config.split {
+            split_tests[test_idx].extend(quote_split_test(
+                &task,
+                test,
+                case,
+                tokens,
+                is_async,
+                config.sequential,
+            ));
+            continue;
+        }
 
         if is_async {
             tasks_async.push(task);
@@ -179,259 +651,727 @@ pub fn impl_suite(id: Ident, item_mod: ItemMod) -> proc_macro2::TokenStream {
     // Call sync implementations.
     let exec_sync = (!tasks_sync.is_empty()).then(|| {
         if config.sequential {
-            quote_seq_exec_sync(&tasks_sync)
+            quote_seq_exec_sync(&tasks_sync, config.fail_fast)
         } else {
-            quote_par_exec_sync(&tasks_sync)
+            quote_par_exec_sync(&tasks_sync, config.fail_fast, config.max_concurrency)
         }
     });
 
     // Call async implementations.
     let exec_async = (!tasks_async.is_empty()).then(|| {
         if config.sequential {
-            quote_seq_exec_async(&tasks_async)
+            quote_seq_exec_async(&tasks_async, config.fail_fast)
         } else {
-            quote_par_exec_async(&tasks_async)
+            quote_par_exec_async(&tasks_async, config.fail_fast, config.max_concurrency)
         }
     });
 
     // Used to trigger the drop implementations of what is stored in the state.
     // testcontainers is one example of where cleanup is sometimes necessary.
+    // The local states are dropped by the outermost suite.
+    let clear_local =
+        (!is_child).then_some(quote!(__LOCAL.iter().for_each(suitest::internal::StateMap::clear);));
     let drop_state = if is_async {
         quote!(
             rt.block_on(rt.spawn(
                 async {
-                    // SAFETY: Nothing is touching the global state at this point.
-                    let items = unsafe { __GLOBAL.drain() };
-                    drop(items);
+                    #clear_local
+                    __GLOBAL.clear();
                 }
             )).expect("unable to spawn drop task");
         )
     } else {
         quote!(
-            // SAFETY: Nothing is touching the global state at this point.
-            let items = unsafe { __GLOBAL.drain() };
-            drop(items);
+            #clear_local
+            __GLOBAL.clear();
+        )
+    };
+
+    // Nested suites run once the tests of this suite finish
+    let rt_arg = match (is_async, is_child) {
+        (false, _) => None,
+        (true, false) => Some(quote!(&rt)),
+        (true, true) => Some(quote!(rt)),
+    };
+    let filter_arg = if is_child {
+        quote!(filter)
+    } else {
+        quote!(&filter)
+    };
+    let run_children = children.iter().map(|child| {
+        let ident = &child.ident;
+        let run = quote!(
+            let child = #ident::__suite(#filter_arg, #rt_arg);
+            errors.extend(child.errors);
+            errored.extend(child.errored);
+            panics.extend(child.panic);
+        );
+        if !config.fail_fast {
+            return run;
+        }
+        let names = child.suite.cases().into_iter().map(
+            |(_, name, ignored)| quote!(if filter.includes(#ignored) { skipped.push(#name); }),
+        );
+        quote!(
+            if errors.is_empty() {
+                #run
+                skipped.extend(child.skipped);
+            } else {
+                #(#names)*
+            }
+        )
+    });
+    let (panics, run_children) = if children.is_empty() {
+        (None, quote!(vec![]))
+    } else {
+        (
+            Some(quote!(let mut panics: Vec<Box<dyn ::std::any::Any + Send + 'static>> = vec![];)),
+            quote!(
+                let mut errored = vec![];
+                #(#run_children)*
+                errored
+            ),
+        )
+    };
+
+    let children = children
+        .into_iter()
+        .map(|child| {
+            let ChildSuite {
+                attrs,
+                vis,
+                ident,
+                suite,
+            } = child;
+            let content = quote_suite(suite, root, &inherited.extend(&own));
+            quote!(
+                #(#attrs)*
+                #vis mod #ident {
+                    #content
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let runner = if config.split {
+        // Cases of parameterized tests are grouped in a module named after the test
+        let split_tests = tests.iter().zip(split_tests).map(|(test, tokens)| {
+            if test.cases[0].ident.is_none() {
+                return tokens;
+            }
+            let ident = &test.item.sig.ident;
+            quote!(
+                mod #ident {
+                    use super::*;
+                    #tokens
+                }
+            )
+        });
+
+        // Each test needs to use the same runtime since the global state
+        // can contain items bound to it.
+        let (runtime, runtime_ref) = if is_async {
+            (
+                Some(quote!(
+                    static __RUNTIME: suitest::internal::once_cell::sync::Lazy<tokio::runtime::Runtime> =
+                        suitest::internal::once_cell::sync::Lazy::new(|| #runtime_builder);
+                )),
+                Some(quote!(let rt = &*__RUNTIME;)),
+            )
+        } else {
+            (None, None)
+        };
+
+        quote!(
+            static __SUITE: suitest::internal::SplitSuite =
+                suitest::internal::SplitSuite::new(module_path!(), &[#(#test_names),*]);
+
+            #runtime
+
+            fn __suite_setup() -> ::std::thread::Result<()> {
+                #runtime_ref
+                __SUITE.setup(|| {
+                    #ba_invoke
+                })
+            }
+
+            fn __suite_finish(name: &'static str, result: &::std::thread::Result<()>) {
+                #runtime_ref
+                if let Some(outcome) = __SUITE.finish(name, result) {
+                    #aa_invoke
+
+                    #drop_state
+
+                    #aa_result
+                }
+            }
+
+            #(#split_tests)*
         )
+    } else {
+        // Tests not executed due to `fail_fast`
+        let (skipped, skipped_list) = if config.fail_fast {
+            (
+                Some(quote!(let mut skipped: Vec<&str> = vec![];)),
+                quote!(skipped),
+            )
+        } else {
+            (None, quote!(vec![]))
+        };
+
+        let setup_failed = if is_child {
+            format!(
+                "setup failed in before_all of {}",
+                prefix.trim_end_matches("::")
+            )
+        } else {
+            String::from("setup failed in before_all")
+        };
+
+        // The tests do not run if `before_all` failed and are reported as errored instead
+        let run = quote!(
+            #test_tasks
+
+            let setup = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+                #ba_invoke
+            }));
+
+            // The panics of the failed tests along with their names
+            let mut errors: Vec<(&str, Box<dyn ::std::any::Any + Send + 'static>)> = vec![];
+
+            #skipped
+
+            #panics
+
+            let errored: Vec<&str> = if setup.is_ok() {
+                #exec_sync
+
+                #exec_async
+
+                #run_children
+            } else {
+                eprintln!(#setup_failed);
+                [#(#test_names),*]
+                    .into_iter()
+                    .filter(|(_, ignored)| filter.includes(*ignored))
+                    .map(|(name, _)| name)
+                    .collect()
+            };
+        );
+
+        if is_child {
+            let rt_param = is_async.then_some(quote!(rt: &tokio::runtime::Runtime));
+            let skipped_list = if config.fail_fast {
+                quote!(skipped.clone())
+            } else {
+                quote!(vec![])
+            };
+            let panics = if panics.is_some() {
+                quote!(.or(panics.pop()))
+            } else {
+                quote!()
+            };
+
+            quote!(
+                #[allow(unused_mut)]
+                pub(super) fn __suite(
+                    filter: &suitest::internal::TestFilter,
+                    #rt_param
+                ) -> suitest::internal::ChildResults {
+                    #run
+
+                    let outcome = suitest::internal::outcome(&errors, errored.clone(), #skipped_list);
+
+                    #aa_invoke
+
+                    #drop_state
+
+                    suitest::internal::ChildResults {
+                        errors,
+                        errored,
+                        skipped: #skipped_list,
+                        panic: setup.err().or(after_all.err()) #panics,
+                    }
+                }
+            )
+        } else {
+            // Since libtest is not aware of the ignored tests in the suite, an ignored
+            // test is generated which runs them when invoked with `--ignored`.
+            let ignored_suite = has_ignored.then(|| {
+                let ignored_id = format_ident!("{suite_id}_ignored");
+                quote!(
+                    #[test]
+                    #[ignore]
+                    fn #ignored_id () {
+                        // The suite already runs the ignored tests
+                        if !suitest::internal::TestFilter::from_env().include_ignored() {
+                            #suite_id();
+                        }
+                    }
+                )
+            });
+            let skipped_report = config.fail_fast.then(|| {
+                quote!(if !skipped.is_empty() {
+                    eprintln!(
+                        "skipped {} test(s) after the first failure: {}",
+                        skipped.len(),
+                        skipped.join(", ")
+                    );
+                })
+            });
+            let child_panics = panics.is_some().then(|| {
+                quote!(if let Some(e) = panics.pop() {
+                    ::std::panic::resume_unwind(e);
+                })
+            });
+
+            quote!(
+                #ignored_suite
+
+                #[test]
+                fn #suite_id () {
+                    #runtime
+
+                    let filter = suitest::internal::TestFilter::from_env();
+
+                    #run
+
+                    #skipped_report
+
+                    let outcome = suitest::internal::outcome(&errors, errored, #skipped_list);
+
+                    #aa_invoke
+
+                    #drop_state
+
+                    #aa_result
+
+                    if let Err(e) = setup {
+                        ::std::panic::resume_unwind(e);
+                    }
+
+                    #child_panics
+
+                    if let Some((_, e)) = errors.pop() {
+                        ::std::panic::resume_unwind(suitest::internal::into_panic(e));
+                    }
+                }
+            )
+        }
     };
 
+    // Nested suites share the local states of the outermost suite
+    let shared_statics = is_child.then(|| {
+        let context = track.then_some(quote!(
+            use super::__CONTEXT;
+        ));
+        quote!(
+            use super::__LOCAL;
+            #context
+        )
+    });
+
+    let Inherited {
+        before_each,
+        after_each,
+        around_each,
+        cleanup,
+    } = own;
+
     quote!(
-        #(#attrs)*
-        #vis #mod_token #ident {
-            #(#other)*
+        #(#other)*
 
-            type __AnyMapValue = ::std::boxed::Box<dyn ::std::any::Any + Send + Sync>;
-            type __AnyMap = ::std::collections::HashMap<::std::any::TypeId, __AnyMapValue>;
-            type __LazyMap = ::suitest::internal::once_cell::sync::Lazy<__AnyMap>;
-            static mut __GLOBAL: __LazyMap = __LazyMap::new(|| __AnyMap::new());
+        #shared_statics
 
-            #local_map
+        static __GLOBAL: suitest::internal::StateMap = suitest::internal::StateMap::new();
 
-            #(const #ids: usize = #id_lits;)*
+        #(const #ids: usize = #id_lits;)*
 
-            #before_all
+        #(#before_all)*
 
-            #before_each
+        #(#before_each)*
 
-            #after_each
+        #(#after_each)*
 
-            #after_all
+        #(#around_each)*
 
-            #test_declarations
+        #(#after_all)*
 
-            #cleanup
+        #test_declarations
 
-            #[test]
-            fn #suite_id () {
-                #runtime
+        #(#cleanup)*
 
-                #test_tasks
+        #(#teardown)*
 
-                #ba_invoke
+        #runner
 
-                let mut errors: Vec<Box<dyn ::std::any::Any + Send + 'static>> = vec![];
+        #(#children)*
+    )
+}
 
-                #exec_sync
+/// Generates a libtest test for a single task of a `split` suite.
+///
+/// The suite hooks are coordinated through the `__SUITE` static, the first test to start
+/// runs `before_all` and the last one to finish runs `after_all`.
+fn quote_split_test(
+    task: &TaskQuote,
+    test: &TestFn,
+    case: &TestCase,
+    task_tokens: proc_macro2::TokenStream,
+    is_async: bool,
+    sequential: bool,
+) -> proc_macro2::TokenStream {
+    let name = &task.name;
 
-                #exec_async
+    let fn_id = case.ident.as_ref().unwrap_or(&test.item.sig.ident);
 
-                if errors.is_empty() {
-                    #aa_invoke
-                }
+    // libtest takes care of ignored tests
+    let ignore = &test.ignore;
 
-                #drop_state
+    let runtime_ref = (is_async || task.cleanup.iter().any(|(_, is_async)| *is_async))
+        .then_some(quote!(let rt = &*__RUNTIME;));
 
-                if let Some(e) = errors.pop() {
-                    ::std::panic::resume_unwind(e);
+    let lock = sequential.then_some(quote!(let _guard = __SUITE.lock();));
+
+    let exec = if is_async {
+        let future = task.quote_future();
+        quote!(rt.block_on(rt.spawn(#future)).map_err(|e| e.into_panic()))
+    } else {
+        task.quote_catch_unwind()
+    };
+
+    let cleanup = task.quote_cleanup(quote!(&**e));
+
+    let msg = format!("{name} ... x");
+
+    quote!(
+        #[test]
+        #ignore
+        fn #fn_id() {
+            #runtime_ref
+            #lock
+
+            #task_tokens
+
+            // Tests do not run if `before_all` failed
+            let result = __suite_setup().and_then(|()| {
+                let result = #exec;
+                if let Err(e) = &result {
+                    eprintln!(#msg);
+                    #cleanup
                 }
+                result
+            });
+
+            __suite_finish(#name, &result);
+
+            if let Err(e) = result {
+                ::std::panic::resume_unwind(suitest::internal::into_panic(e));
             }
         }
     )
 }
 
-fn quote_seq_exec_async(tasks: &[TaskQuote]) -> proc_macro2::TokenStream {
+fn quote_seq_exec_async(tasks: &[TaskQuote], fail_fast: bool) -> proc_macro2::TokenStream {
     let mut tokens = quote!();
     for task in tasks {
-        let id = &task.id;
-        let fn_id = &task.fn_id.to_string();
-        let const_id = &task.const_id;
-        let cleanup = task.cleanup.as_ref().map(|(cleanup, is_async)| {
-            if *is_async {
-                quote!(rt.block_on(#cleanup::<#const_id>());)
-            } else {
-                quote!(#cleanup::<#const_id>();)
-            }
-        });
-        tokens.extend(quote!(
-            let result = rt.block_on(rt.spawn(#id));
-            if let Err(e) = result {
-                eprintln!("{} ... x", #fn_id);
-                #cleanup
-                errors.push(e.into_panic());
-            }
+        let future = task.quote_future();
+        let name = &task.name;
+        let cleanup = task.quote_cleanup(quote!(&*e));
+        tokens.extend(quote_selected(
+            task,
+            quote_fail_fast(
+                task,
+                fail_fast,
+                quote!(
+                    let result = rt.block_on(rt.spawn(#future)).map_err(|e| e.into_panic());
+                    if let Err(e) = result {
+                        eprintln!("{} ... x", #name);
+                        #cleanup
+                        errors.push((#name, e));
+                    }
+                ),
+            ),
         ));
     }
     tokens
 }
 
-fn quote_par_exec_async(tasks: &[TaskQuote]) -> proc_macro2::TokenStream {
-    let spawns = tasks.iter().map(|t| {
-        let id = &t.id;
-        quote!(rt.spawn(::std::boxed::Box::pin(#id)),)
+fn quote_par_exec_async(
+    tasks: &[TaskQuote],
+    fail_fast: bool,
+    max_concurrency: Option<usize>,
+) -> proc_macro2::TokenStream {
+    // Tasks wait for a permit before running when the concurrency is bounded
+    let semaphore = max_concurrency.map(|max| {
+        quote!(
+            let semaphore = ::std::sync::Arc::new(suitest::internal::Semaphore::new(#max));
+        )
     });
 
-    let msg = tasks.iter().map(|t| {
-        let id = format!("{} ... x", &t.fn_id);
-        quote!(#id)
+    let spawns = tasks.iter().map(|t| {
+        let (mut future, const_id) = (t.quote_future(), &t.const_id);
+        if max_concurrency.is_some() {
+            future = quote!(suitest::internal::limit(semaphore.clone(), #future));
+        }
+        quote_selected(
+            t,
+            quote_fail_fast(
+                t,
+                fail_fast,
+                quote!(handles.push((#const_id, rt.spawn(::std::boxed::Box::pin(#future))));),
+            ),
+        )
     });
 
-    let cleanups = tasks.iter().map(|t| {
-        let const_id = &t.const_id;
-        t.cleanup.as_ref().map(|(cleanup, is_async)| {
-            let cleanup = if *is_async {
-                quote!(rt.block_on(#cleanup::<#const_id>());)
-            } else {
-                quote!(#cleanup::<#const_id>();)
-            };
-            quote!(if i == #const_id { #cleanup })
+    // Aborted tasks are reported as skipped
+    let aborted = tasks.iter().map(|t| {
+        let name = &t.name;
+        fail_fast.then(|| {
+            quote!(
+                if e.is_cancelled() {
+                    skipped.push(#name);
+                    continue;
+                }
+            )
         })
     });
 
+    let const_ids = tasks.iter().map(|t| &t.const_id);
+
+    let msgs = tasks.iter().map(|t| {
+        let msg = format!("{} ... x", t.name);
+        quote!(eprintln!(#msg);)
+    });
+
+    let cleanups = tasks.iter().map(|t| t.quote_cleanup(quote!(&*e)));
+
+    let names = tasks.iter().map(|t| &t.name);
+
     quote!(
-        let results = rt.block_on(
-            suitest::internal::futures_util::future::join_all(
-                vec![#(#spawns)*]
-            )
-        );
+        #semaphore
+        let mut handles = vec![];
+        #(#spawns)*
 
-        let msgs = [
-            #(#msg),*
-        ];
+        let results = rt.block_on(suitest::internal::join_tasks(handles, #fail_fast));
 
-        for (i, result) in results.into_iter().enumerate() {
+        for (id, result) in results {
             if let Err(e) = result {
-                eprintln!("{}", msgs[i]);
-                #(#cleanups)*
-                errors.push(e.into_panic());
+                let (name, e) = match id {
+                    #(
+                        #const_ids => {
+                            #aborted
+                            let e = e.into_panic();
+                            #msgs
+                            #cleanups
+                            (#names, e)
+                        }
+                    )*
+                    _ => unreachable!()
+                };
+                errors.push((name, e));
             }
         }
     )
 }
 
-fn quote_par_exec_sync(tasks: &[TaskQuote]) -> proc_macro2::TokenStream {
+fn quote_par_exec_sync(
+    tasks: &[TaskQuote],
+    fail_fast: bool,
+    max_concurrency: Option<usize>,
+) -> proc_macro2::TokenStream {
+    // The pool stops handing out tasks once one of them fails, with a worker per task
+    // the tests still run all at once
+    match max_concurrency {
+        Some(max) => return quote_pool_exec_sync(tasks, fail_fast, max),
+        None if fail_fast => return quote_pool_exec_sync(tasks, fail_fast, tasks.len()),
+        None => {}
+    }
+
     let task_invokes = tasks.iter().map(|t| {
-        let (id, thread_id) = (&t.id, t.fn_id.to_string());
-        quote!(
-            let thread = ::std::thread::Builder::new().name(#thread_id.to_string());
-            handles.push(thread.spawn(#id).expect("could not spawn test thread"));
-        )
+        let (spawn, const_id) = (t.quote_spawn(), &t.const_id);
+        quote_selected(t, quote!(handles.push((#const_id, #spawn));))
     });
 
-    let const_ids = tasks.iter().map(|t| &t.const_id).collect::<Vec<_>>();
-    let cleanups = tasks.iter().zip(const_ids.iter()).map(|(t, const_id)| {
-        t.cleanup.as_ref().map(|(cleanup, is_async)| {
-            if *is_async {
-                quote!(rt.block_on(#cleanup::<#const_id>());)
-            } else {
-                quote!(#cleanup::<#const_id>();)
-            }
-        })
-    });
+    let const_ids = tasks.iter().map(|t| &t.const_id);
+
+    let cleanups = tasks.iter().map(|t| t.quote_cleanup(quote!(&*e)));
+
+    let names = tasks.iter().map(|t| &t.name);
 
     let msgs = tasks.iter().map(|t| {
-        let msg = format!("{} ... x", t.fn_id);
+        let msg = format!("{} ... x", t.name);
         quote!(eprintln!(#msg);)
     });
 
     quote!(
         let mut handles = vec![];
         #(#task_invokes)*
-        for (i, handle) in handles.into_iter().enumerate() {
+        for (id, handle) in handles {
             let result = handle.join();
             if let Err(e) = result {
-                match i {
+                let name = match id {
                     #(
                         #const_ids => {
                             #msgs
                             #cleanups
-                            errors.push(e);
+                            #names
                         }
                     )*
                     _ => unreachable!()
-                }
+                };
+                errors.push((name, e));
+            }
+        }
+    )
+}
+
+/// Runs the sync tasks on a worker pool so that at most `max` of them run at the same time.
+fn quote_pool_exec_sync(
+    tasks: &[TaskQuote],
+    fail_fast: bool,
+    max: usize,
+) -> proc_macro2::TokenStream {
+    let task_pushes = tasks.iter().map(|t| {
+        let (id, const_id, name, timeout) = (&t.id, &t.const_id, &t.name, t.quote_timeout());
+        quote_selected(
+            t,
+            quote_fail_fast(
+                t,
+                fail_fast,
+                quote!(pool.push(#const_id, #name, #timeout, #id);),
+            ),
+        )
+    });
+
+    let const_ids = tasks.iter().map(|t| &t.const_id);
+
+    let cleanups = tasks.iter().map(|t| t.quote_cleanup(quote!(&*e)));
+
+    let names = tasks.iter().map(|t| &t.name);
+
+    let msgs = tasks.iter().map(|t| {
+        let msg = format!("{} ... x", t.name);
+        quote!(eprintln!(#msg);)
+    });
+
+    // Tasks that never started are reported as skipped
+    let (not_run, skip_not_run) = if fail_fast {
+        (quote!(not_run), Some(quote!(skipped.extend(not_run);)))
+    } else {
+        (quote!(_), None)
+    };
+
+    quote!(
+        let mut pool = suitest::internal::WorkerPool::new(#max, #fail_fast);
+        #(#task_pushes)*
+        let (results, #not_run) = pool.run();
+        #skip_not_run
+        for (id, result) in results {
+            if let Err(e) = result {
+                let name = match id {
+                    #(
+                        #const_ids => {
+                            #msgs
+                            #cleanups
+                            #names
+                        }
+                    )*
+                    _ => unreachable!()
+                };
+                errors.push((name, e));
             }
         }
     )
 }
 
-fn quote_seq_exec_sync(tasks: &[TaskQuote]) -> proc_macro2::TokenStream {
+fn quote_seq_exec_sync(tasks: &[TaskQuote], fail_fast: bool) -> proc_macro2::TokenStream {
     let mut tokens = quote!();
 
     for task in tasks {
-        let TaskQuote {
-            id,
-            const_id,
-            cleanup,
-            ..
-        } = task;
+        let exec = task.quote_catch_unwind();
 
-        let cleanup = cleanup.as_ref().map(|(cleanup, is_async)| {
-            if *is_async {
-                quote!(rt.block_on(#cleanup::<#const_id>());)
-            } else {
-                quote!(#cleanup::<#const_id>();)
-            }
-        });
+        let cleanup = task.quote_cleanup(quote!(&*e));
 
-        let msg = format!("{} ... x", task.fn_id);
+        let name = &task.name;
+        let msg = format!("{name} ... x");
         let msg = quote!(println!(#msg););
 
-        tokens.extend(quote!(
-           let result = ::std::panic::catch_unwind(#id);
-           if let Err(e) = result {
-            #msg
-            #cleanup
-            errors.push(e);
-           }
+        tokens.extend(quote_selected(
+            task,
+            quote_fail_fast(
+                task,
+                fail_fast,
+                quote!(
+                   let result = #exec;
+                   if let Err(e) = result {
+                    #msg
+                    #cleanup
+                    errors.push((#name, e));
+                   }
+                ),
+            ),
         ));
     }
 
     tokens
 }
 
-/// Returns the new fn definition as the first element and the ident of that fn as the second. If the ident is `None`, the function
-/// should not be invoked in the test suite (happens only the the `suite_fn` input argument is `None`).
+/// Wraps the task execution so it is skipped if any of the previous tests failed.
+fn quote_fail_fast(
+    task: &TaskQuote,
+    fail_fast: bool,
+    tokens: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if !fail_fast {
+        return tokens;
+    }
+
+    let name = &task.name;
+    quote!(
+        if errors.is_empty() {
+            #tokens
+        } else {
+            skipped.push(#name);
+        }
+    )
+}
+
+/// Wraps the task execution so it only runs if it is selected by the libtest `--ignored`
+/// and `--include-ignored` flags.
+fn quote_selected(task: &TaskQuote, tokens: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let ignored = task.ignored;
+    let skipped = ignored.then(|| {
+        let msg = format!("{} ... ignored", task.name);
+        quote!(else { println!(#msg); })
+    });
+
+    quote!(
+        if filter.includes(#ignored) {
+            #tokens
+        } #skipped
+    )
+}
+
+/// Returns the new fn definition along with its ident and what is needed to invoke it.
 ///
-/// The first element ultimately replaces the original fn, while the second is used when running the test suite.
+/// The definition ultimately replaces the original fn, while the rest is used when running the test suite.
 fn quote_suite_fn(
-    suite_fn: Option<&SuiteFn>,
+    suite_fn: &SuiteFn,
+    fixture: Option<&Fixture>,
     local: bool,
+    depth: usize,
     print_msg: Option<&str>,
-) -> Option<FnQuote> {
+) -> FnQuote {
     let SuiteFn {
         item,
         modifier,
         inputs,
-    } = suite_fn?;
+        timeout,
+        context,
+        test_context,
+        ..
+    } = suite_fn;
 
     let ItemFn {
         attrs,
@@ -442,7 +1382,15 @@ fn quote_suite_fn(
 
     let block_stmts = &block.stmts;
 
-    let state_getters = quote_state_getters(&sig.ident, inputs, local, print_msg.is_some());
+    let state_getters = quote_state_getters(
+        &sig.ident,
+        inputs,
+        &suite_fn.type_checks,
+        local,
+        depth,
+        sig.asyncness.is_some(),
+        print_msg.is_some(),
+    );
 
     let state_setters = modifier
         .as_ref()
@@ -454,6 +1402,7 @@ fn quote_suite_fn(
         fn_token,
         ident,
         generics,
+        output,
         ..
     } = sig;
 
@@ -462,111 +1411,358 @@ fn quote_suite_fn(
     let local_id = local.then_some(quote!(const LOCAL_ID: usize,));
     let print = print_msg.map(|m| quote!(println!(#m);));
 
+    let test_context = test_context.as_ref().map(|PatType { pat, ty, .. }| {
+        let reference = matches!(**ty, Type::Reference(_)).then_some(quote!(&));
+        quote!(let #pat: #ty = #reference __CONTEXT[LOCAL_ID].context(LOCAL_ID);)
+    });
+
+    // Fallible hooks are left returning `Result<(), E>`
+    let is_fallible = !matches!(output, ReturnType::Default);
+
+    // The original block computes the return value in its own scope so it can return early,
+    // which then gets inserted to the state
+    let body = match modifier {
+        Some(StateModifier { output: ty, .. }) => {
+            let computed = if asyncness.is_some() {
+                quote!(async move { #(#block_stmts)* }.await)
+            } else {
+                quote!((move || -> #ty { #(#block_stmts)* })())
+            };
+            let unwrap = is_fallible.then_some(quote!(let __output = __output?;));
+            let ok = is_fallible.then_some(quote!(::std::result::Result::Ok(())));
+            quote!(
+                let __output: #ty = #computed;
+                #unwrap
+                #state_setters
+                #ok
+            )
+        }
+        None => quote!(#(#block_stmts)*),
+    };
+
+    // Hooks of fixtures are invoked from the suite module
+    let vis = match fixture {
+        Some(_) => quote!(pub(super)),
+        None => quote!(#vis),
+    };
+
+    // The types of the returned items are aliased so the readers can name them in their type checks
+    let alias_vis = fixture.map(|_| quote!(pub(super)));
+    let aliases = modifier
+        .iter()
+        .flat_map(StateModifier::items)
+        .zip(suite_fn.item_aliases())
+        .map(|((ty, _), alias)| {
+            quote!(
+                #[allow(dead_code, non_camel_case_types)]
+                #alias_vis type #alias = #ty;
+            )
+        });
+
     let tokens = quote!(
+        #(#aliases)*
+
         #(#attrs)*
-        #vis #asyncness #constness #fn_token #ident < #local_id #(#consts)* #(#tys)* > ()  {
+        #vis #asyncness #constness #fn_token #ident < #local_id #(#consts)* #(#tys)* > (#context) #output {
             #print
+            #test_context
             #state_getters
-            #(#block_stmts)*
-            #state_setters
+            #body
+        }
+    );
+
+    // Hooks of fixtures are generated in their own module to keep the imports of the fixtures apart
+    let (tokens, path) = match (fixture, suite_fn.fixture_module()) {
+        (Some(Fixture { path, imports, .. }), Some(module)) => {
+            let imports = imports
+                .iter()
+                .map(|import| quote_fixture_import(path, import));
+            let path = quote_fixture_path(path);
+            (
+                quote!(
+                    #[allow(unused_imports)]
+                    mod #module {
+                        use super::*;
+                        use #path::*;
+                        #(#imports)*
+
+                        #tokens
+                    }
+                ),
+                quote!(#module::),
+            )
         }
+        _ => (tokens, quote!()),
+    };
+
+    let mut fn_quote = FnQuote::new(
+        tokens,
+        ident.clone(),
+        sig.asyncness.is_some(),
+        is_fallible,
+        *timeout,
     );
+    fn_quote.has_context = context.is_some();
+    fn_quote.locks_shared = locks_shared(inputs);
+    fn_quote.path = path;
+    fn_quote
+}
+
+/// The path to the `fixtures` module from the module generated for one of its hooks,
+/// which is declared in the suite module.
+fn quote_fixture_path(path: &syn::Path) -> proc_macro2::TokenStream {
+    let (leading_colon, segments) = (&path.leading_colon, fixture_segments(path));
+    quote!(#leading_colon #(#segments)::*)
+}
+
+/// The segments of the path to the `fixtures` module from the module generated for one of its hooks.
+fn fixture_segments(path: &syn::Path) -> Vec<Ident> {
+    let segments = path.segments.iter().map(|seg| seg.ident.clone());
+    if path.leading_colon.is_some() || path.segments[0].ident == "crate" {
+        return segments.collect();
+    }
+    let mut relative = vec![format_ident!("super"), format_ident!("super")];
+    relative.extend(segments.skip_while(|seg| seg == "self"));
+    relative
+}
+
+/// Imports of the `fixtures` module starting with `self` or `super` are made relative to the
+/// module generated for the hook, the remaining ones are kept as they are.
+fn quote_fixture_import(path: &syn::Path, import: &ItemUse) -> proc_macro2::TokenStream {
+    let ItemUse {
+        attrs,
+        vis,
+        use_token,
+        leading_colon,
+        tree,
+        semi_token,
+    } = import;
+    let tree = if leading_colon.is_some() {
+        quote!(#tree)
+    } else {
+        quote_fixture_use_tree(&fixture_segments(path), tree)
+    };
+    quote!(#(#attrs)* #vis #use_token #leading_colon #tree #semi_token)
+}
 
-    Some(FnQuote::new(tokens, ident.clone(), sig.asyncness.is_some()))
+fn quote_fixture_use_tree(module: &[Ident], tree: &UseTree) -> proc_macro2::TokenStream {
+    match tree {
+        UseTree::Path(UsePath { ident, .. }) if ident == "self" || ident == "super" => {
+            let mut base = module.to_vec();
+            let mut tree = tree;
+            while let UseTree::Path(UsePath {
+                ident, tree: rest, ..
+            }) = tree
+            {
+                if ident == "super" {
+                    match base.last() {
+                        Some(last) if last == "crate" => abort!(
+                            ident.span(),
+                            "fixtures cannot import from outside of the crate"
+                        ),
+                        Some(last) if last != "super" => {
+                            base.pop();
+                        }
+                        _ => base.push(ident.clone()),
+                    }
+                } else if ident != "self" {
+                    break;
+                }
+                tree = rest;
+            }
+            quote!(#(#base::)* #tree)
+        }
+        UseTree::Group(UseGroup { items, .. }) => {
+            let items = items
+                .iter()
+                .map(|tree| quote_fixture_use_tree(module, tree));
+            quote!({ #(#items),* })
+        }
+        tree => quote!(#tree),
+    }
 }
 
 /// Use the original fn arguments to prepend state getters to the function block.
+///
+/// Items missing from the global state of a nested suite are looked up in the global states
+/// of the `depth` suites it is declared in.
+fn locks_shared(input: &Punctuated<FnArg, Comma>) -> bool {
+    input
+        .iter()
+        .any(|arg| StateArg::parse(arg).access == Access::Shared)
+}
+
 fn quote_state_getters(
     fn_id: &Ident,
     input: &Punctuated<FnArg, Comma>,
+    type_checks: &[TypeCheck],
     local: bool,
+    depth: usize,
+    is_async: bool,
     verbose: bool,
 ) -> proc_macro2::TokenStream {
     let mut tokens = quote!();
 
-    input.pairs().map(|pair| pair.into_value()).for_each(|val| {
-        let FnArg::Typed(pt) = val else {
-            abort!(val.span(), "suitest functions cannot take in `self`")
-        };
+    input.pairs().map(|pair| pair.into_value()).enumerate().for_each(|(i, val)| {
+        let arg = StateArg::parse(val);
 
-        let (Pat::Ident(id), ty) = (&*pt.pat, &pt.ty) else {
-            abort!(
-                val.span(),
-                "suitest functions accept only `id: type` pairs, e.g. `foo: T`"
-            )
+        // Patterns are bound to the item first and destructured from it, `_` only requires the item
+        let (id, handle, destructure) = match arg.pat {
+            Pat::Ident(ref id) => (quote!(#id), id.ident.clone(), None),
+            Pat::Wild(_) => {
+                let id = format_ident!("__arg{i}");
+                (quote!(#id), id, None)
+            }
+            ref pat => {
+                let id = format_ident!("__arg{i}");
+                (quote!(#id), id.clone(), Some(quote!(let #pat = #id;)))
+            }
         };
 
+        let ty = &arg.ty;
+
         // Print statements
-        let ty_display = type_display(None, ty);
+        let ty_display = match arg.name {
+            Some(ref name) => format!("{} named '{}'", type_display(ty), name.value()),
+            None => type_display(ty),
+        };
         let expect = format!("unitialised item '{ty_display}' at '{fn_id}'");
         let local_miss =
             format!("{fn_id} - {ty_display} not found in local state, getting from global");
         let local_miss = verbose.then_some(quote!(println!(#local_miss);));
 
-        let getters = if local {
-            quote!(
-                // SAFETY: Multiple immutable references are fine.
-                // We are never modifying the __GLOBAL state other
-                // than in *_all hooks, only reading from it.
-                let #id: &#ty = unsafe {
-                    let item = __LOCAL[LOCAL_ID]
-                        .get(&::std::any::TypeId::of::<#ty>())
-                        .or_else(|| {
+        let name = match arg.name {
+            Some(ref name) => quote!(::std::option::Option::Some(#name)),
+            None => quote!(::std::option::Option::None),
+        };
+
+        // The global states from the innermost suite outwards
+        let global = |ty: proc_macro2::TokenStream| {
+            let parents = (1..=depth).map(|depth| {
+                let path = (0..depth).map(|_| quote!(super::));
+                quote!(.or_else(|| #(#path)* __GLOBAL.get_named::<#ty>(#name)))
+            });
+            quote!(__GLOBAL.get_named::<#ty>(#name) #(#parents)*)
+        };
+
+        // The handle keeps the item alive while the reference is in use
+        let (getters, from) = match arg.access {
+            Access::Ref | Access::Clone => {
+                let global = global(quote!(#ty));
+                let (lookup, from) = if local {
+                    (
+                        quote!(__LOCAL[LOCAL_ID].get_named::<#ty>(#name).or_else(|| {
                             #local_miss
-                            __GLOBAL.get(&::std::any::TypeId::of::<#ty>())
-                        })
-                        .expect(#expect);
-                    item
-                        .downcast_ref::<#ty>()
-                        .unwrap_or_else(||
-                            panic!(
-                                    "downcast to '{}' failed; expected '{:?}', found '{:?}'",
-                                    #ty_display,
-                                    ::std::any::TypeId::of::<#ty>(),
-                                    item.type_id()
-                            )
-                        )
+                            #global
+                        })),
+                        "local",
+                    )
+                } else {
+                    (global, "global")
                 };
-            )
-        } else {
-            quote!(
-                // SAFETY: Multiple immutable references are fine.
-                // We are never modifying the global state other
-                // than in *_all hooks, only reading from it.
-                let #id: &#ty = unsafe {
-                    let item = __GLOBAL
-                        .get(&::std::any::TypeId::of::<#ty>())
-                        .expect(#expect);
-                    item
-                        .downcast_ref::<#ty>()
-                        .unwrap_or_else(||
-                            panic!(
-                                    "downcast to '{}' failed; expected '{:?}', found '{:?}'",
-                                    #ty_display,
-                                    ::std::any::TypeId::of::<#ty>(),
-                                    item.type_id()
-                            )
-                        )
+                let getters = match (arg.access, arg.optional) {
+                    (Access::Clone, true) => quote!(
+                        let #id: ::std::option::Option<#ty> = #lookup.map(|item| <#ty as ::std::clone::Clone>::clone(&*item));
+                    ),
+                    (Access::Clone, false) => quote!(
+                        let #id: #ty = <#ty as ::std::clone::Clone>::clone(&*#lookup.expect(#expect));
+                    ),
+                    (_, true) => quote!(
+                        let #handle = #lookup;
+                        let #id: ::std::option::Option<&#ty> = #handle.as_deref();
+                    ),
+                    (_, false) => quote!(
+                        let #handle = #lookup.expect(#expect);
+                        let #id: &#ty = &#handle;
+                    ),
                 };
-            )
+                (getters, from)
+            }
+            Access::Take => {
+                let getters = if arg.optional {
+                    quote!(let #id: ::std::option::Option<#ty> = __LOCAL[LOCAL_ID].take::<#ty>(#name);)
+                } else {
+                    quote!(let #id: #ty = __LOCAL[LOCAL_ID].take::<#ty>(#name).expect(#expect);)
+                };
+                (getters, "local")
+            }
+            // The guard puts the item back to the state once the function returns
+            Access::Mut => (
+                quote!(
+                    let mut #handle = __LOCAL[LOCAL_ID].get_mut::<#ty>(#name).expect(#expect);
+                    let #id: &mut #ty = &mut #handle;
+                ),
+                "local",
+            ),
+            Access::Shared => {
+                let lock = if is_async {
+                    quote!(#handle.lock_owned().await)
+                } else {
+                    quote!(suitest::internal::lock_blocking(#handle))
+                };
+                let global = global(quote!(suitest::internal::Shared<#ty>));
+                (
+                    quote!(
+                        let #handle = #global.expect(#expect);
+                        let mut #handle = #lock;
+                        let #id: &mut #ty = &mut #handle;
+                    ),
+                    "shared",
+                )
+            }
         };
 
-        let printed = format!(
-            "{fn_id} - getting {} from {} state",
-            ty_display,
-            if local { "local" } else { "global" }
-        );
+        let printed = format!("{fn_id} - getting {ty_display} from {from} state");
         let print = verbose.then_some(quote!(println!(#printed);));
 
+        let type_check = type_checks
+            .iter()
+            .find(|check| check.arg == i)
+            .map(|check| quote_type_check(ty, check));
+
         tokens.extend(quote!(
+            #type_check
             #print
             #getters
+            #destructure
         ))
     });
 
     tokens
 }
 
+/// Asserts that the type is one of the candidates of the check, failing to compile otherwise.
+///
+/// Each candidate implements its own trait at a different level of references, so the method
+/// resolves to the first candidate matching the type and duplicate candidates are not ambiguous.
+fn quote_type_check(
+    ty: &Type,
+    TypeCheck { candidates, .. }: &TypeCheck,
+) -> proc_macro2::TokenStream {
+    let impls = candidates.iter().enumerate().map(|(i, candidate)| {
+        let provided = format_ident!("__Provided{}", i);
+        let refs = (0..i).map(|_| quote!(&));
+        quote!(
+            trait #provided {
+                fn is_provided(&self) {}
+            }
+            impl #provided for #(#refs)* StateItem<#candidate> {}
+        )
+    });
+    let refs = (1..candidates.len()).map(|_| quote!(&));
+    let probe = quote_spanned!(ty.span()=>
+        (#(#refs)* StateItem::<#ty>(::std::marker::PhantomData)).is_provided();
+    );
+
+    quote!(
+        {
+            struct StateItem<T>(::std::marker::PhantomData<T>);
+            #(#impls)*
+            #probe
+        }
+    )
+}
+
 /// Use the state modifiers to append statements to the function block that insert the specified data to the state.
 fn quote_state_setters(
     fn_id: &Ident,
@@ -575,68 +1771,47 @@ fn quote_state_setters(
     verbose: bool,
 ) -> proc_macro2::TokenStream {
     let state_map = if local {
-        quote!(let state = unsafe { &mut __LOCAL[LOCAL_ID] };)
+        quote!(let state = &__LOCAL[LOCAL_ID];)
     } else {
-        quote!(let state = unsafe { &mut __GLOBAL };)
+        quote!(let state = &__GLOBAL;)
     };
 
-    match (&modifier.fn_output, &modifier.last_block_item) {
-        (PathOrTupleReturn::Path(ret_path), PathOrTupleExpr::Path(expr_path)) => {
-            use ::std::fmt::Write;
-
-            let mut result = String::new();
-
-            for (i, seg) in ret_path.path.segments.iter().enumerate() {
-                if i == ret_path.path.segments.len() - 1 {
-                    write!(result, "{}", seg.ident).unwrap()
-                } else {
-                    write!(result, "{}::", seg.ident).unwrap()
-                }
-            }
-
+    match modifier.fn_output {
+        HookOutput::Single(ref ty) => {
+            let ty_display = type_display(ty);
             let printed = format!(
-                "{fn_id} - setting {result} to {} state",
+                "{fn_id} - setting {ty_display} to {} state",
                 if local { "local" } else { "global" }
             );
 
             let printed = verbose.then_some(quote!(println!(#printed);));
 
+            let insert = quote_insert(
+                &modifier.names[0],
+                modifier.shared[0],
+                quote!(#ty),
+                quote!(__output),
+            );
+
             quote!(
             {
                 #printed
                 #state_map
-                let boxed = Box::new(#expr_path);
-                let type_id = ::std::any::TypeId::of::<#ret_path>();
-                assert_eq!(type_id, <#ret_path as ::std::any::Any>::type_id(&*boxed), "type mismatch; check test hooks for correct types");
-                state.insert(type_id, boxed);
+                #insert;
             })
         }
-        (PathOrTupleReturn::Tuple(ret_tup), PathOrTupleExpr::Tuple(expr_tup)) => {
+        HookOutput::Tuple(ref ret_tup) => {
             let ret_elems = ret_tup
                 .elems
                 .pairs()
                 .map(|pair| pair.into_value())
                 .collect::<Vec<&Type>>();
 
-            let expr_elems = expr_tup
-                .elems
-                .pairs()
-                .map(|pair| pair.into_value())
-                .collect::<Vec<&Expr>>();
-
-            if ret_elems.len() != expr_elems.len() {
-                abort!(
-                    modifier.span(),
-                    "return value mismatch, expected {} items in return",
-                    ret_elems.len()
-                )
-            }
-
             let printed = ret_elems.iter().map(|el| {
                 if !verbose {
                     return None;
                 }
-                let ty_display = type_display(None, el);
+                let ty_display = type_display(el);
                 let msg = format!(
                     "{fn_id} - setting {ty_display} to {} state",
                     if local { "local" } else { "global" }
@@ -644,28 +1819,63 @@ fn quote_state_setters(
                 Some(quote!(println!(#msg);))
             });
 
+            let inserts = ret_elems
+                .iter()
+                .enumerate()
+                .zip(modifier.names.iter().zip(&modifier.shared))
+                .map(|((i, ty), (name, shared))| {
+                    let i = syn::Index::from(i);
+                    quote_insert(name, *shared, quote!(#ty), quote!(__output.#i))
+                });
+
             quote!(
                 #state_map
                 #({
                     #printed
-                    let boxed = Box::new(#expr_elems);
-                    let type_id = ::std::any::TypeId::of::<#ret_elems>();
-                    assert_eq!(type_id, <#ret_elems as ::std::any::Any>::type_id(&*boxed), "type mismatch; check test hooks for correct types");
-                    state.insert(type_id, boxed);
+                    #inserts;
                 })*
             )
         }
-        _ => {
-            abort!(modifier.span(), "return value mismatch")
-        }
+    }
+}
+
+/// Inserts the item to the `state` in scope, under its name if it has one.
+/// Shared items are inserted behind a lock.
+fn quote_insert(
+    name: &Option<LitStr>,
+    shared: bool,
+    ty: proc_macro2::TokenStream,
+    item: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let (ty, item) = if shared {
+        (
+            quote!(suitest::internal::Shared<#ty>),
+            quote!(suitest::internal::Shared::new(#item)),
+        )
+    } else {
+        (ty, item)
+    };
+    match name {
+        Some(name) => quote!(state.insert_named::<#ty>(::std::option::Option::Some(#name), #item)),
+        None => quote!(state.insert::<#ty>(#item)),
     }
 }
 
 /// Generates new test functions with the inputs removed and the state getters configured.
-fn quote_test_declarations(tests: &[TestFn], verbose: bool) -> proc_macro2::TokenStream {
+fn quote_test_declarations(
+    tests: &[TestFn],
+    depth: usize,
+    verbose: bool,
+) -> proc_macro2::TokenStream {
     let mut tokens = quote!();
     tests.iter().for_each(|test| {
-        let TestFn { item, inputs, .. } = test;
+        let TestFn {
+            item,
+            inputs,
+            params,
+            ..
+        } = test;
+        let impl_id = test.impl_id();
         let ItemFn {
             attrs,
             vis,
@@ -681,13 +1891,22 @@ fn quote_test_declarations(tests: &[TestFn], verbose: bool) -> proc_macro2::Toke
             fn_token,
             ident,
             generics,
+            output,
             ..
         } = sig;
 
         let tys = generics.type_params();
         let consts = generics.const_params();
 
-        let state_getters = quote_state_getters(ident, inputs, true, verbose);
+        let state_getters = quote_state_getters(
+            ident,
+            inputs,
+            &test.type_checks,
+            true,
+            depth,
+            asyncness.is_some(),
+            verbose,
+        );
 
         let new_attrs = attrs.iter().filter(|attr|!attr.meta.path().is_ident("test"));
 
@@ -696,7 +1915,7 @@ fn quote_test_declarations(tests: &[TestFn], verbose: bool) -> proc_macro2::Toke
 
         let toks =quote!(
             #(#new_attrs)*
-            #vis #asyncness #constness #fn_token #ident < const LOCAL_ID: usize, #(#consts)* #(#tys)* > ()  {
+            #vis #asyncness #constness #fn_token #impl_id < const LOCAL_ID: usize, #(#consts)* #(#tys)* > (#params) #output {
                 #print
                 #state_getters
                 #(#block_stmts)*
@@ -708,35 +1927,3 @@ fn quote_test_declarations(tests: &[TestFn], verbose: bool) -> proc_macro2::Toke
 
     tokens
 }
-
-fn type_display(prefix: Option<&str>, ty: &syn::Type) -> String {
-    use ::std::fmt::Write;
-    match ty {
-        syn::Type::Array(arr) => type_display(Some("array of "), &arr.elem),
-        syn::Type::Group(g) => type_display(Some("group of "), &g.elem),
-        syn::Type::Paren(p) => type_display(None, &p.elem),
-        syn::Type::Path(p) => {
-            let mut result = prefix.unwrap_or_default().to_string();
-            for (i, seg) in p.path.segments.iter().enumerate() {
-                if i == p.path.segments.len() - 1 {
-                    write!(result, "{}", seg.ident).unwrap()
-                } else {
-                    write!(result, "{}::", seg.ident).unwrap()
-                }
-            }
-            result
-        }
-        syn::Type::Ptr(p) => type_display(Some("pointer to "), &p.elem),
-        syn::Type::Reference(r) => type_display(Some("reference to "), &r.elem),
-        syn::Type::Slice(s) => type_display(Some("slice of "), &s.elem),
-        syn::Type::Tuple(t) => {
-            let mut result = prefix.unwrap_or_default().to_string();
-            for ty in t.elems.iter() {
-                let ty_str = type_display(None, ty);
-                write!(result, "{ty_str}").unwrap();
-            }
-            result
-        }
-        _ => abort!(ty.span(), "type cannot be used in suitest hook"),
-    }
-}
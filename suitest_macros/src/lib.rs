@@ -1,4 +1,5 @@
 use proc_macro_error::proc_macro_error;
+use suite::{SuiteArgs, UseFixtures};
 use syn::ItemMod;
 
 mod r#impl;
@@ -6,9 +7,13 @@ mod suite;
 
 /// Annotate a test module to enable hook annotations.
 /// Requires one parameter - the name of the test suite.
-/// The whole test suite will contain only one function as far as cargo is concerned.
+/// The whole test suite will contain only one function as far as cargo is concerned,
+/// unless the suite is configured with `split = true`.
 /// To run with output for individual tests, invoking `cargo test` with `--nocapture` is required.
 ///
+/// Inline modules in the suite can be annotated with `#[suitest::suite(child)]` to nest suites.
+/// Nested suites inherit the config, the global state and the per-test hooks of their parents.
+///
 /// ## Example
 ///
 /// ```ignore
@@ -42,8 +47,68 @@ pub fn suite(
 ) -> proc_macro::TokenStream {
     let suite =
         syn::parse::<ItemMod>(input.clone()).expect("suitest can only be used on `mod` items");
-    let id = syn::parse(attr).expect("invalid suite identifier");
-    r#impl::impl_suite(id, suite).into()
+    let SuiteArgs { id, fixtures } = syn::parse(attr).expect("invalid suite identifier");
+    r#impl::impl_use_fixtures(UseFixtures {
+        id,
+        pending: fixtures,
+        fixtures: vec![],
+        item_mod: suite,
+    })
+    .into()
+}
+
+/// Annotate a module to declare hooks which can be used by multiple suites.
+///
+/// Suites list the fixtures they use with `use = path::to::fixtures`, which can be repeated
+/// to compose multiple fixtures. The hooks of the fixtures run before the hooks of the suite
+/// of the same kind, in the order the fixtures are listed. It is a compile error for the
+/// `before_*` hooks of different fixtures or of the suite to return the same item.
+///
+/// The hooks are removed from the module and generated in each suite using them,
+/// so the items they use must be visible to the suite, e.g. declared as `pub(crate)`.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[suitest::fixtures]
+/// mod database {
+///   use suitest::{before_all, after_all};
+///
+///   #[before_all]
+///   fn connect() -> Pool {
+///     Pool::connect(DATABASE_URL)
+///   }
+///
+///   #[after_all]
+///   fn close(pool: Pool) {
+///     pool.close();
+///   }
+/// }
+///
+/// #[suitest::suite(users, use = crate::database)]
+/// mod users {
+///   #[test]
+///   fn lists_users(pool: Pool) {
+///     pool.query("SELECT * FROM users");
+///   }
+/// }
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn fixtures(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let fixtures = syn::parse::<ItemMod>(input).expect("fixtures can only be used on `mod` items");
+    r#impl::impl_fixtures(fixtures).into()
+}
+
+#[doc(hidden)]
+#[proc_macro]
+#[proc_macro_error]
+pub fn __fixtures(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as UseFixtures);
+    r#impl::impl_use_fixtures(input).into()
 }
 
 /// Annotate the suite module to configure the suite.
@@ -51,6 +116,24 @@ pub fn suite(
 /// `sequential = bool [false]` - Run the suite in sequence or in parallel.
 ///
 /// `verbose = bool [false]` - Print what's going on when running the suite
+///
+/// `split = bool [false]` - Register each test as a separate libtest test
+///
+/// `timeout = "duration" [none]` - Fail tests that do not finish in the given duration, e.g. `"30s"`
+///
+/// `retries = usize [0]` - Rerun failing tests up to the given number of times
+///
+/// `fail_fast = bool [false]` - Skip the remaining tests after the first failure
+///
+/// `max_concurrency = usize [unbounded]` - The maximum number of tests running at the same time,
+/// sync tests that time out no longer count towards it
+///
+/// `runtime = "flavor" | path ["current_thread"]` - The tokio runtime flavor, `"current_thread"` or `"multi_thread"`,
+/// or a path to a `fn() -> tokio::runtime::Runtime`
+///
+/// `worker_threads = usize [tokio default]` - The number of worker threads of the `"multi_thread"` runtime
+///
+/// `start_paused = bool [false]` - Start the `"current_thread"` runtime with its clock paused, requires the tokio `test-util` feature
 #[proc_macro_attribute]
 #[proc_macro_error]
 pub fn suite_cfg(
@@ -63,10 +146,20 @@ pub fn suite_cfg(
 /// The annotated function runs when starting the test suite only once. Useful
 /// for setting up the global state.
 ///
+/// A suite can have multiple `before_all` hooks, running in declaration order. Each can read
+/// the items returned from the ones before it. The order can be changed with `#[before_all(order = N)]`,
+/// lower orders run first and the default is 0.
+///
 /// To insert items to the global state, add a single type or n-tuple return value
 /// consisting of the types you wish to insert.
 /// Then, add them as function arguments in any test/hook from which you wish to retrieve them.
 ///
+/// The hook can also return a `Result` with the values to insert in its `Ok` variant. If it returns
+/// an error, the suite fails with the error's debug output.
+///
+/// If the hook fails, none of the tests run and they are reported as errored. The `teardown` hooks
+/// still run and the state is dropped.
+///
 /// ## Example
 ///
 /// ```ignore
@@ -100,6 +193,11 @@ pub fn before_all(
 ///
 /// The function can read the global state.
 ///
+/// If the hook fails, its test is reported as errored rather than failed and `cleanup` runs.
+///
+/// Like `before_all`, multiple hooks run in declaration order unless
+/// ordered with `#[before_each(order = N)]`, and can read the local items returned from the ones before them.
+///
 /// ## Example
 ///
 /// ```ignore
@@ -138,6 +236,9 @@ pub fn before_each(
 /// The annotated function runs only once when all the tests have passed.
 ///
 /// Can only read the global state.
+///
+/// Multiple hooks run in reverse declaration order, or in reverse
+/// of their `#[after_all(order = N)]`, so the hooks declared first run last.
 #[proc_macro_attribute]
 #[proc_macro_error]
 pub fn after_all(
@@ -151,6 +252,9 @@ pub fn after_all(
 /// state.
 ///
 /// Can read the local state from `before_each` as well as the global state.
+/// The hook can take a `TestContext` parameter describing the test and its attempt.
+///
+/// Multiple hooks run in reverse declaration order, or in reverse of their `#[after_each(order = N)]`.
 #[proc_macro_attribute]
 #[proc_macro_error]
 pub fn after_each(
@@ -160,10 +264,43 @@ pub fn after_each(
     input
 }
 
+/// The annotated function wraps each test, running after `before_each` and before `after_each`.
+/// Useful for holding guards, transactions or tracing spans for the duration of the test.
+///
+/// The test is passed as the only `impl Trait` parameter, an `impl Future<Output = ()>` for async hooks
+/// and an `impl FnOnce()` for sync ones. Async tests can only be wrapped by async hooks.
+/// The test fails if the hook returns without running it to completion.
+/// Can read the local state from `before_each` as well as the global state.
+///
+/// Multiple hooks are nested in declaration order, or by their `#[around_each(order = N)]`,
+/// so the hook declared first is the outermost one.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[around_each]
+/// async fn transaction(test: impl Future<Output = ()>, db: Db) {
+///     let tx = db.begin().await;
+///     test.await;
+///     tx.rollback().await;
+/// }
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn around_each(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    input
+}
+
 /// The annotated function runs whenever a test panics. Useful for having a
 /// safety net to clear state if a test fails.
 ///
 /// Can read from the local state from `before_each` as well as the global state.
+/// The hook can take a `TestContext` parameter describing the failed test, including its panic message.
+///
+/// Multiple hooks run in reverse declaration order, or in reverse of their `#[cleanup(order = N)]`.
 #[proc_macro_attribute]
 #[proc_macro_error]
 pub fn cleanup(
@@ -172,3 +309,145 @@ pub fn cleanup(
 ) -> proc_macro::TokenStream {
     input
 }
+
+/// The annotated function runs once after all the tests, regardless of whether they passed.
+/// Useful for collecting logs and stopping external processes even when the suite fails.
+///
+/// Runs after `after_all`, even if it or another `teardown` panics, and can only read the global state.
+/// The global state is dropped before the panic is resumed.
+/// Since the hook also runs if `before_all` fails, consider requesting its items as `Option<T>`.
+/// The hook can take a `&SuiteOutcome` parameter describing which tests failed.
+/// Multiple hooks run in reverse declaration order, or in reverse of their `#[teardown(order = N)]`.
+///
+/// ## Example
+///
+/// ```ignore
+/// use suitest::{teardown, SuiteOutcome};
+///
+/// #[teardown]
+/// fn stop_server(server: Server, outcome: &SuiteOutcome) {
+///     if !outcome.passed() {
+///         eprintln!("failed: {:?}\n{}", outcome.failed(), server.logs());
+///     }
+///     server.stop();
+/// }
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn teardown(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    input
+}
+
+/// Parameterizes a test. Each case runs as a separate test with its own local state
+/// and `before_each`/`after_each` invocations, reported as `my_test::case_N`.
+///
+/// The case arguments are bound positionally to the leading test parameters,
+/// the remaining parameters are obtained from the state as usual.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[before_all]
+/// fn setup() -> String {
+///     let s = String::from("Hello world");
+///     s
+/// }
+///
+/// #[test]
+/// #[case(1, "Hello")]
+/// #[case(2, "world")]
+/// fn my_test(n: usize, part: &str, s: String) {
+///     assert!(n > 0);
+///     assert!(s.contains(part));
+/// }
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn case(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    input
+}
+
+/// Fails the annotated test or hook if it does not finish within the given duration.
+/// Accepts non-zero durations in `ms`, `s`, `m` and `h`. Overrides the suite `timeout` on tests.
+///
+/// The timeout of a test applies to the test along with its `before_each` and `after_each` hooks.
+/// Sync tests and hooks with a timeout run on a separate thread which is left running if it times out.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[test]
+/// #[timeout("5s")]
+/// async fn my_test() {
+///     tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+/// }
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn timeout(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    input
+}
+
+/// Reruns the annotated test up to the given number of times if it fails.
+/// Overrides the suite `retries`.
+///
+/// Each attempt runs `before_each`, the test and `after_each` with a fresh local state
+/// and gets its own `timeout`. The `cleanup` hook runs after each failed attempt.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[test]
+/// #[retry(3)]
+/// fn flaky() {
+///     assert!(some_flaky_service());
+/// }
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn retry(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    input
+}
+
+/// Names the items returned from a `before_*` hook, one name for each item.
+/// Items named `_` are inserted without a name.
+///
+/// Named items are obtained by annotating the argument with `#[state("name")]`,
+/// allowing the state to hold multiple items of the same type.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[before_all]
+/// #[name("primary", "replica")]
+/// fn setup() -> (Pool, Pool) {
+///     let primary = Pool::connect(PRIMARY_URL);
+///     let replica = Pool::connect(REPLICA_URL);
+///     (primary, replica)
+/// }
+///
+/// #[test]
+/// fn reads(#[state("replica")] pool: Pool) {
+///     pool.query("SELECT 1");
+/// }
+/// ```
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn name(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    input
+}
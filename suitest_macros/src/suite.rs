@@ -1,9 +1,15 @@
-use proc_macro_error::abort;
-use quote::{quote, ToTokens};
+use proc_macro_error::{abort, emit_error};
+use quote::{format_ident, quote, ToTokens};
+use std::{num::IntErrorKind, time::Duration};
 use syn::{
-    parse::ParseBuffer, punctuated::Punctuated, spanned::Spanned, token::Comma, Attribute, Expr,
-    ExprLit, ExprPath, ExprTuple, FnArg, Ident, Item, ItemFn, Lit, MetaNameValue, ReturnType,
-    Token, TypePath, TypeTuple,
+    braced, bracketed,
+    parse::{Parse, ParseBuffer, ParseStream},
+    punctuated::Punctuated,
+    spanned::Spanned,
+    token::Comma,
+    Attribute, Expr, ExprLit, ExprPath, ExprUnary, FnArg, GenericArgument, Ident, Item, ItemFn,
+    ItemMod, ItemUse, Lifetime, Lit, LitInt, LitStr, Meta, MetaNameValue, Pat, PatType,
+    PathArguments, ReturnType, Token, Type, TypeParamBound, TypeTuple, UnOp, Visibility,
 };
 
 // Suite markers
@@ -12,19 +18,49 @@ pub const BEFORE_ALL: &str = "before_all";
 pub const AFTER_ALL: &str = "after_all";
 pub const BEFORE_EACH: &str = "before_each";
 pub const AFTER_EACH: &str = "after_each";
+pub const AROUND_EACH: &str = "around_each";
 pub const CLEANUP: &str = "cleanup";
+pub const TEARDOWN: &str = "teardown";
 pub const ANNOTATIONS: &[&str] = &[
     TEST,
     BEFORE_ALL,
     BEFORE_EACH,
     AFTER_ALL,
     AFTER_EACH,
+    AROUND_EACH,
     CLEANUP,
+    TEARDOWN,
 ];
 
+// Suite markers for nested suites
+const SUITE: &str = "suite";
+const SUITE_CFG: &str = "suite_cfg";
+
+// Test attributes
+const CASE: &str = "case";
+const SHOULD_PANIC: &str = "should_panic";
+const IGNORE: &str = "ignore";
+const EXPECTED: &str = "expected";
+const TIMEOUT: &str = "timeout";
+const RETRY: &str = "retry";
+
+// State attributes
+const NAME: &str = "name";
+const STATE: &str = "state";
+const SHARED: &str = "shared";
+const CLONE: &str = "clone";
+const TAKE: &str = "take";
+
 // Configuration markers
 const VERBOSE: &str = "verbose";
 const SEQUENTIAL: &str = "sequential";
+const SPLIT: &str = "split";
+const RETRIES: &str = "retries";
+const FAIL_FAST: &str = "fail_fast";
+const MAX_CONCURRENCY: &str = "max_concurrency";
+const RUNTIME: &str = "runtime";
+const WORKER_THREADS: &str = "worker_threads";
+const START_PAUSED: &str = "start_paused";
 
 #[derive(Debug)]
 pub struct TestSuite {
@@ -37,26 +73,57 @@ pub struct TestSuite {
     /// The test functions to run in the suite.
     pub tests: Vec<TestFn>,
 
-    /// The first function that runs in the test suite.
-    pub before_all: Option<SuiteFn>,
+    /// The first functions that run in the test suite.
+    pub before_all: Vec<SuiteFn>,
+
+    /// The functions that run before each test in the test suite.
+    pub before_each: Vec<SuiteFn>,
 
-    /// The function that runs before each test in the test suite.
-    pub before_each: Option<SuiteFn>,
+    /// The last functions that run in the test suite.
+    pub after_all: Vec<SuiteFn>,
 
-    /// The last function that runs in the test suite.
-    pub after_all: Option<SuiteFn>,
+    /// The functions that run after each test in the test suite.
+    pub after_each: Vec<SuiteFn>,
 
-    /// The function that runs after each test in the test suite.
-    pub after_each: Option<SuiteFn>,
+    /// The functions wrapping the execution of each test in the test suite.
+    pub around_each: Vec<SuiteFn>,
 
-    /// The function to call after a test fails.
-    pub cleanup: Option<SuiteFn>,
+    /// The functions to call after a test fails.
+    pub cleanup: Vec<SuiteFn>,
 
-    /// Used to determine whether to generate the runtime tokens.
+    /// The functions that run after all the tests regardless of their outcome.
+    pub teardown: Vec<SuiteFn>,
+
+    /// Used to determine whether to generate the runtime tokens, includes the nested suites.
     pub is_async: bool,
 
     // Holds all non-test items such as structs, imports, etc.
     pub other_items: Vec<Item>,
+
+    /// The suites nested in this one, running after its tests.
+    pub children: Vec<ChildSuite>,
+
+    /// The number of suites this one is nested in.
+    pub depth: usize,
+
+    /// Prepended to the names of the tests of nested suites, e.g. `parent::child::`.
+    pub prefix: String,
+
+    /// The `fixtures` modules the suite uses, in the order they are listed.
+    pub fixtures: Vec<Fixture>,
+}
+
+/// A suite declared as a module inside another suite.
+#[derive(Debug)]
+pub struct ChildSuite {
+    /// The remaining attributes of the module.
+    pub attrs: Vec<Attribute>,
+
+    pub vis: Visibility,
+
+    pub ident: Ident,
+
+    pub suite: TestSuite,
 }
 
 impl TestSuite {
@@ -65,21 +132,62 @@ impl TestSuite {
             suite_id: id,
             config,
             tests: vec![],
-            before_all: None,
-            before_each: None,
-            after_all: None,
-            after_each: None,
-            cleanup: None,
+            before_all: vec![],
+            before_each: vec![],
+            after_all: vec![],
+            after_each: vec![],
+            around_each: vec![],
+            cleanup: vec![],
+            teardown: vec![],
             is_async: false,
             other_items: vec![],
+            children: vec![],
+            depth: 0,
+            prefix: String::new(),
+            fixtures: vec![],
+        }
+    }
+
+    /// Parse the hooks of a `fixtures` module. They are processed the same way as the hooks
+    /// declared in the suite and are marked with the fixture so they can be generated in its scope.
+    pub fn process_fixture(&mut self, id: &mut usize, mut fixture: Fixture) {
+        let index = self.fixtures.len();
+        for hook in std::mem::take(&mut fixture.hooks) {
+            let lens = self.hooks_mut().map(|hooks| hooks.len());
+            self.process_item(id, Item::Fn(hook));
+            for (hooks, len) in self.hooks_mut().into_iter().zip(lens) {
+                for hook in hooks[len..].iter_mut() {
+                    hook.fixture = Some(index);
+                }
+            }
         }
+        self.fixtures.push(fixture);
+    }
+
+    fn hooks_mut(&mut self) -> [&mut Vec<SuiteFn>; 7] {
+        [
+            &mut self.before_all,
+            &mut self.before_each,
+            &mut self.after_all,
+            &mut self.after_each,
+            &mut self.around_each,
+            &mut self.cleanup,
+            &mut self.teardown,
+        ]
     }
 
     pub fn process_item(&mut self, id: &mut usize, item: Item) {
-        // We are interested only in functions
-        let syn::Item::Fn(mut item) = item else {
-            self.other_items.push(item);
-            return;
+        // We are interested only in functions and nested suites
+        let mut item = match item {
+            Item::Fn(item) => item,
+            Item::Mod(item) if item.attrs.iter().any(|attr| is_attr(attr, SUITE)) => {
+                self.process_child(id, item);
+                return;
+            }
+            item => {
+                self.other_items.push(item);
+                return;
+            }
         };
 
         if !item.attrs.iter().any(|a| {
@@ -96,6 +204,18 @@ impl TestSuite {
         // be collecting these from the state
         let inputs = std::mem::take(&mut item.sig.inputs);
 
+        let timeout = take_timeout(&mut item.attrs);
+
+        let is_before = item
+            .attrs
+            .iter()
+            .any(|attr| is_attr(attr, BEFORE_ALL) || is_attr(attr, BEFORE_EACH));
+        if let Some(attr) = item.attrs.iter().find(|attr| is_attr(attr, NAME)) {
+            if !is_before {
+                abort!(attr.span(), "`#[name]` can only be used on before_* hooks")
+            }
+        }
+
         if item.sig.asyncness.is_some() {
             self.is_async = true;
         }
@@ -107,45 +227,92 @@ impl TestSuite {
 
             match attr_ident.ident.to_string().as_str() {
                 TEST => {
-                    if !matches!(item.sig.output, ReturnType::Default) {
-                        abort!(item.sig.output.span(), "tests cannot return values")
+                    if let ReturnType::Type(_, ref ty) = item.sig.output {
+                        // The `Ok` value would be discarded
+                        let is_unit =
+                            |ok: &Type| matches!(ok, Type::Tuple(t) if t.elems.is_empty());
+                        if !split_result(ty).is_some_and(|(ok, _)| is_unit(&ok)) {
+                            abort!(ty.span(), "tests can only return `Result<(), E>`")
+                        }
                     }
-                    self.tests.push(TestFn::new(*id, item, inputs));
-                    *id += 1;
+                    self.tests.push(TestFn::new(id, item, inputs, timeout));
                     break;
                 }
                 BEFORE_ALL => {
+                    let order = parse_order(attr);
                     let modifier = Self::extract_fn_modifier(&mut item);
-                    self.before_all = Some(SuiteFn::new(item, inputs));
-                    if let Some(modifier) = modifier {
-                        self.before_all.as_mut().unwrap().set_modifier(modifier);
-                    }
+                    self.before_all
+                        .push(SuiteFn::new(item, inputs, timeout, order, modifier));
                     break;
                 }
                 BEFORE_EACH => {
+                    let order = parse_order(attr);
                     let modifier = Self::extract_fn_modifier(&mut item);
-                    self.before_each = Some(SuiteFn::new(item, inputs));
-                    if let Some(modifier) = modifier {
-                        self.before_each.as_mut().unwrap().set_modifier(modifier);
-                    }
+                    self.before_each
+                        .push(SuiteFn::new(item, inputs, timeout, order, modifier));
                     break;
                 }
                 AFTER_ALL => {
                     if !matches!(item.sig.output, ReturnType::Default) {
                         abort!(item.sig.output.span(), "after_* hooks cannot return values")
                     }
-                    self.after_all = Some(SuiteFn::new(item, inputs));
+                    let order = parse_order(attr);
+                    self.after_all
+                        .push(SuiteFn::new(item, inputs, timeout, order, None));
                     break;
                 }
                 AFTER_EACH => {
                     if !matches!(item.sig.output, ReturnType::Default) {
                         abort!(item.sig.output.span(), "after_* hooks cannot return values")
                     }
-                    self.after_each = Some(SuiteFn::new(item, inputs));
+                    let order = parse_order(attr);
+                    let (inputs, test_context) = take_context(inputs, "TestContext");
+                    let mut hook = SuiteFn::new(item, inputs, timeout, order, None);
+                    hook.test_context = test_context;
+                    self.after_each.push(hook);
+                    break;
+                }
+                AROUND_EACH => {
+                    if !matches!(item.sig.output, ReturnType::Default) {
+                        abort!(
+                            item.sig.output.span(),
+                            "around_each hooks cannot return values"
+                        )
+                    }
+                    if timeout.is_some() {
+                        abort!(
+                            item.sig.ident.span(),
+                            "around_each hooks cannot have a timeout";
+                            help = "the timeout of the test applies to its around_each hooks"
+                        )
+                    }
+                    let order = parse_order(attr);
+                    let (inputs, test) = take_wrapped_test(&item, inputs);
+                    let mut hook = SuiteFn::new(item, inputs, None, order, None);
+                    hook.context = Some(test);
+                    self.around_each.push(hook);
                     break;
                 }
                 CLEANUP => {
-                    self.cleanup = Some(SuiteFn::new(item, inputs));
+                    let order = parse_order(attr);
+                    let (inputs, test_context) = take_context(inputs, "TestContext");
+                    let mut hook = SuiteFn::new(item, inputs, timeout, order, None);
+                    hook.test_context = test_context;
+                    self.cleanup.push(hook);
+                    break;
+                }
+                TEARDOWN => {
+                    if !matches!(item.sig.output, ReturnType::Default) {
+                        abort!(
+                            item.sig.output.span(),
+                            "teardown hooks cannot return values"
+                        )
+                    }
+                    let order = parse_order(attr);
+                    let (inputs, outcome) = take_outcome(inputs);
+                    let mut hook = SuiteFn::new(item, inputs, timeout, order, None);
+                    hook.context = outcome;
+                    self.teardown.push(hook);
                     break;
                 }
                 _ => {}
@@ -153,60 +320,688 @@ impl TestSuite {
         }
     }
 
-    /// Extract the fn's return value and last block statement into a state modifier. Modifies the original item by
-    /// removing its return value and popping the last statement in the function block.
+    /// Parse a module annotated with `suite` as a nested suite. Its tests are given local
+    /// state identifiers following the ones of the parent and it inherits the parent's config.
+    fn process_child(&mut self, id: &mut usize, item: ItemMod) {
+        let ItemMod {
+            mut attrs,
+            vis,
+            ident,
+            content,
+            ..
+        } = item;
+
+        if let Some(attr) = attrs.iter().find(|attr| is_attr(attr, SUITE_CFG)) {
+            abort!(
+                attr.span(),
+                "nested suites use the configuration of the suite they are declared in";
+                help = "move the configuration to the outermost suite"
+            )
+        }
+
+        let position = attrs.iter().position(|attr| is_attr(attr, SUITE)).unwrap();
+        let attr = attrs.remove(position);
+        let args = attr.parse_args::<SuiteArgs>().unwrap_or_else(|_| {
+            abort!(
+                attr.span(),
+                "expected the identifier of the nested suite, e.g. `#[suitest::suite(child)]`"
+            )
+        });
+        if let Some(path) = args.fixtures.first() {
+            abort!(
+                path.span(),
+                "fixtures can only be used by the outermost suite";
+                help = "nested suites can use the hooks of the suites they are declared in"
+            )
+        }
+        let suite_id = args.id;
+
+        let Some((_, items)) = content else {
+            abort!(ident.span(), "nested suites must be declared inline")
+        };
+
+        let mut suite = TestSuite::new(suite_id, self.config.clone());
+        suite.depth = self.depth + 1;
+        // Nested tests are named after the whole path of suites, starting with the outermost one
+        let parent = match self.depth {
+            0 => format!("{}::", self.suite_id),
+            _ => self.prefix.clone(),
+        };
+        suite.prefix = format!("{parent}{}::", suite.suite_id);
+
+        for item in items {
+            suite.process_item(id, item);
+        }
+
+        self.is_async |= suite.is_async;
+        self.children.push(ChildSuite {
+            attrs,
+            vis,
+            ident,
+            suite,
+        });
+    }
+
+    /// The cases of the suite and its nested suites as their local state identifiers, their
+    /// names used when reporting and whether they are ignored.
+    pub fn cases(&self) -> Vec<(usize, String, bool)> {
+        let mut cases = self
+            .tests
+            .iter()
+            .flat_map(|test| {
+                test.cases.iter().map(move |case| {
+                    let name = format!("{}{}", self.prefix, case.name(&test.item.sig.ident));
+                    (case.id, name, test.ignore.is_some())
+                })
+            })
+            .collect::<Vec<_>>();
+        for child in self.children.iter() {
+            cases.extend(child.suite.cases());
+        }
+        cases
+    }
+
+    /// Whether any of the hooks in the suite or its nested suites request the `TestContext`.
+    pub fn tracks(&self) -> bool {
+        self.after_each
+            .iter()
+            .chain(self.cleanup.iter())
+            .any(|hook| hook.test_context.is_some())
+            || self.children.iter().any(|child| child.suite.tracks())
+    }
+
+    /// Extract the fn's return value into a state modifier. Modifies the original item by removing
+    /// its return value, the block is later wrapped so it computes the value before it gets inserted
+    /// to the state.
+    ///
+    /// If the fn returns a `Result`, the modifier is extracted from its `Ok` value and the fn
+    /// is left returning `Result<(), E>`.
     fn extract_fn_modifier(item: &mut ItemFn) -> Option<StateModifier> {
-        let fn_output = match item.sig.output {
+        let names = item
+            .attrs
+            .iter()
+            .position(|attr| is_attr(attr, NAME))
+            .map(|i| item.attrs.remove(i));
+
+        let (arrow, output) = match item.sig.output {
             // If the fn does not return anything, it will not modify the test suite state
             ReturnType::Default => {
+                if let Some(names) = names {
+                    abort!(names.span(), "`#[name]` requires the hook to return values")
+                }
                 return None;
             }
-            ReturnType::Type(_, ref ty) => match **ty {
-                syn::Type::Path(ref p) => PathOrTupleReturn::Path(p.clone()),
-                syn::Type::Tuple(ref t) => PathOrTupleReturn::Tuple(t.clone()),
-                _ => abort!(
-                    ty.span(),
-                    "before_* hooks must return owned values (or tuples of)"
-                ),
-            },
+            ReturnType::Type(arrow, ref ty) => (arrow, ty.as_ref().clone()),
         };
 
-        // Skip if empty
-        let last_stmt = item.block.stmts.pop()?;
-
-        // Do not abort if the fn does not return anything
-        if let ReturnType::Default = item.sig.output {
-            return None;
-        }
+        let (ty, fallible_output) = match split_result(&output) {
+            Some((ok, fallible)) => (ok, Some(fallible)),
+            None => (output.clone(), None),
+        };
 
-        let last_block_item = match last_stmt {
-            // Matches
-            syn::Stmt::Expr(ref expr, tok) if tok.is_none() => match expr {
-                syn::Expr::Path(ref p) => PathOrTupleExpr::Path(p.clone()),
-                syn::Expr::Tuple(ref t) => PathOrTupleExpr::Tuple(t.clone()),
-                _ => abort!(
-                    last_stmt.span(),
-                    "before_* hooks must return owned values (or tuples of)"
-                ),
-            },
+        verify_static(&ty);
 
-            // We only accept expressions on before_* hooks
+        let fn_output = match ty {
+            Type::Tuple(ref t) => HookOutput::Tuple(t.clone()),
+            Type::Path(_) | Type::BareFn(_) | Type::Array(_) | Type::Paren(_) | Type::Group(_) => {
+                HookOutput::Single(ty.clone())
+            }
             _ => abort!(
-                last_stmt.span(),
+                ty.span(),
                 "before_* hooks must return owned values (or tuples of)"
             ),
         };
 
-        // Modify the output since we popped the last expression in the block
-        item.sig.output = ReturnType::Default;
+        // Nothing to insert for `()` and `Result<(), E>`, the block is left as is
+        if matches!(fn_output, HookOutput::Tuple(ref t) if t.elems.is_empty()) {
+            if let Some(names) = names {
+                abort!(names.span(), "`#[name]` requires the hook to return values")
+            }
+            if fallible_output.is_none() {
+                item.sig.output = ReturnType::Default;
+            }
+            return None;
+        }
+
+        item.sig.output = match fallible_output {
+            Some(fallible) => ReturnType::Type(arrow, Box::new(fallible)),
+            None => ReturnType::Default,
+        };
+
+        let count = match fn_output {
+            HookOutput::Single(_) => 1,
+            HookOutput::Tuple(ref t) => t.elems.len(),
+        };
+
+        let names = match names {
+            Some(attr) => parse_names(&attr, count),
+            None => vec![None; count],
+        };
 
         Some(StateModifier {
             fn_output,
-            last_block_item,
+            output,
+            shared: vec![false; names.len()],
+            names,
+        })
+    }
+
+    /// Check that the `before_*` hooks of the fixtures do not return the same items as each
+    /// other or as the hooks of the suite, since only one of them could be read from the state.
+    pub fn verify_fixtures(&self) {
+        for hooks in [&self.before_all, &self.before_each] {
+            let mut providers: Vec<(StateKey, Option<usize>)> = vec![];
+            for hook in hooks {
+                let Some(ref modifier) = hook.modifier else {
+                    continue;
+                };
+                for (ty, name) in modifier.items() {
+                    let key = (type_key(&ty), name.as_ref().map(LitStr::value));
+                    let conflict = providers
+                        .iter()
+                        .find(|(k, fixture)| *k == key && *fixture != hook.fixture);
+                    if let Some((_, other)) = conflict {
+                        let source = |fixture: &Option<usize>| match fixture {
+                            Some(i) => format!("`{}`", path_display(&self.fixtures[*i].path)),
+                            None => String::from("the suite"),
+                        };
+                        let item = match name {
+                            Some(ref name) => {
+                                format!("`{}` named '{}'", type_display(&ty), name.value())
+                            }
+                            None => format!("`{}`", type_display(&ty)),
+                        };
+                        emit_error!(
+                            ty,
+                            "{} is returned from both {} and {}",
+                            item,
+                            source(other),
+                            source(&hook.fixture);
+                            help = "name the items with `#[name]` to keep both of them"
+                        );
+                    }
+                    providers.push((key, hook.fixture));
+                }
+            }
+        }
+    }
+
+    /// Sort the hooks of each kind by their order. Hooks with the same order keep
+    /// their declaration order.
+    pub fn sort_hooks(&mut self) {
+        for hooks in self.hooks_mut() {
+            hooks.sort_by_key(|hook| hook.order);
+        }
+        for child in self.children.iter_mut() {
+            child.suite.sort_hooks();
+        }
+    }
+
+    /// Check that every item requested by the hooks and tests is returned from a hook
+    /// whose state is visible to them. Items from `before_all` are visible everywhere
+    /// except in the preceding `before_all` hooks, while items from `before_each` are visible
+    /// to tests, `around_each`, `after_each`, `cleanup` and the following `before_each` hooks.
+    ///
+    /// Items requested with `#[shared]` are marked so `before_all` inserts them behind a lock.
+    ///
+    /// Items requested with `&mut T` or `#[take]` are removed from the state while in use, so they
+    /// cannot be requested again by the same function, or by the `around_each` hooks wrapping a test.
+    /// Items taken by a test are gone for good, so `after_each` and `cleanup` can only request them
+    /// as `Option<T>`.
+    ///
+    /// Nested suites additionally see the items `inherited` from the `before_all` and `before_each`
+    /// hooks of the suites they are declared in, along with the items read by their `around_each`,
+    /// `after_each` and `cleanup` hooks. Returns the items requested with `#[shared]` in this suite and its nested suites.
+    ///
+    /// Types are compared by their spelling since aliases cannot be resolved at this point.
+    /// An argument spelled differently from the items with the same name is left for rustc
+    /// to match with a [TypeCheck].
+    pub fn verify_state(&mut self, inherited: &InheritedState) -> Vec<StateKey> {
+        let depth = self.depth;
+        let provided = |hooks: &[SuiteFn]| {
+            hooks
+                .iter()
+                .map(|hook| {
+                    let module = hook.fixture_module().map(|module| quote!(#module::));
+                    hook.modifier
+                        .iter()
+                        .flat_map(StateModifier::items)
+                        .zip(hook.item_aliases())
+                        .map(|((ty, name), alias)| Provided {
+                            key: (type_key(&ty), name.map(|name| name.value())),
+                            ty: type_display(&ty),
+                            depth,
+                            alias: quote!(#module #alias),
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The items provided by each hook
+        let before_all = provided(&self.before_all);
+        let before_each = provided(&self.before_each);
+
+        let InheritedState {
+            global: inherited_global,
+            each: inherited_each,
+            around: inherited_around,
+            after: inherited_after,
+        } = inherited;
+        let (inherited_global, inherited_each) =
+            (inherited_global.as_slice(), inherited_each.as_slice());
+        let global = [inherited_global, &before_all.concat()].concat();
+        let each = [inherited_each, &before_each.concat()].concat();
+        let local = [global.as_slice(), &each].concat();
+
+        let global_hint = "return it from `before_all`";
+        let local_hint = "return it from `before_all` or `before_each`";
+
+        // The functions reading from the state, the items visible to them, the items of the
+        // local state visible to them and whether they run in the scope of a test
+        let mut readers = vec![];
+        for (i, hook) in self.before_all.iter().enumerate() {
+            let hint = if i == 0 && inherited_global.is_empty() {
+                "the first `before_all` hook runs first and cannot read from the state"
+            } else {
+                "return it from a preceding `before_all` hook"
+            };
+            let visible = [inherited_global, &before_all[..i].concat()].concat();
+            readers.push((hook, visible, vec![], hint, false));
+        }
+        for (i, hook) in self.before_each.iter().enumerate() {
+            let local = [inherited_each, &before_each[..i].concat()].concat();
+            let visible = [global.as_slice(), &local].concat();
+            readers.push((hook, visible, local, local_hint, true));
+        }
+        for hook in self.after_all.iter().chain(self.teardown.iter()) {
+            readers.push((hook, global.clone(), vec![], global_hint, false));
+        }
+        for hook in self
+            .around_each
+            .iter()
+            .chain(self.after_each.iter())
+            .chain(self.cleanup.iter())
+        {
+            readers.push((hook, local.clone(), each.clone(), local_hint, true));
+        }
+
+        // Hooks of fixtures are generated in a module of their own
+        let readers = readers
+            .into_iter()
+            .map(|(hook, visible, local, hint, is_local)| {
+                (
+                    &hook.item.sig.ident,
+                    &hook.inputs,
+                    visible,
+                    local,
+                    hint,
+                    is_local,
+                    hook.fixture.is_some(),
+                )
+            })
+            .chain(self.tests.iter().map(|test| {
+                (
+                    &test.item.sig.ident,
+                    &test.inputs,
+                    local.clone(),
+                    each.clone(),
+                    local_hint,
+                    true,
+                    false,
+                )
+            }))
+            .map(
+                |(fn_id, inputs, visible, local, hint, is_local, in_fixture)| {
+                    let args = inputs.iter().map(StateArg::parse).collect::<Vec<_>>();
+                    (fn_id, args, visible, local, hint, is_local, in_fixture)
+                },
+            )
+            .collect::<Vec<_>>();
+
+        // The items read by the hooks running along with the tests, outermost first
+        let reads = |hooks: Vec<&SuiteFn>, inherited: &[HookRead]| {
+            let own = hooks.into_iter().flat_map(|hook| {
+                hook.inputs.iter().map(StateArg::parse).map(|arg| HookRead {
+                    hook: hook.item.sig.ident.clone(),
+                    item: arg.item(),
+                    access: arg.access,
+                    optional: arg.optional,
+                })
+            });
+            inherited.iter().cloned().chain(own).collect::<Vec<_>>()
+        };
+        let around = reads(self.around_each.iter().collect(), inherited_around);
+        let after = reads(
+            self.after_each.iter().chain(&self.cleanup).collect(),
+            inherited_after,
+        );
+
+        // Nested suites can request the items of this suite with `#[shared]`
+        let mut shared = vec![];
+        let nested = InheritedState {
+            global: global.clone(),
+            each: each.clone(),
+            around: around.clone(),
+            after: after.clone(),
+        };
+        for child in self.children.iter_mut() {
+            shared.extend(child.suite.verify_state(&nested));
+        }
+        shared.extend(
+            readers
+                .iter()
+                .flat_map(|(_, args, ..)| args)
+                .filter(|arg| arg.access == Access::Shared)
+                .map(StateArg::key),
+        );
+
+        // The items from `before_each` visible to each reader are checked for local access
+        let mut type_checks = vec![];
+        for (fn_id, args, visible, each, hint, is_local, in_fixture) in readers.iter() {
+            let mut checks = vec![];
+            for (i, arg) in args.iter().enumerate() {
+                let key = arg.key();
+
+                // Only the later of the two arguments is reported
+                if args[..i].iter().any(|other| arg.conflicts(other)) {
+                    emit_error!(
+                        arg.decl,
+                        "{} is requested by `{}` more than once",
+                        arg.describe(),
+                        fn_id;
+                        help = REMOVED_HELP
+                    )
+                }
+
+                let lookup = match arg.access {
+                    Access::Ref | Access::Clone => arg.lookup(visible),
+                    Access::Mut | Access::Take => arg.lookup(each),
+                    Access::Shared if visible.is_empty() => arg.lookup(&[]),
+                    Access::Shared => arg.lookup(&global),
+                };
+                let missing = matches!(lookup, Lookup::Missing);
+                match arg.access {
+                    Access::Ref | Access::Clone if !arg.optional && missing => {
+                        emit_error!(
+                            arg.decl,
+                            "{} is not provided to `{}`",
+                            arg.describe(),
+                            fn_id;
+                            help = hint
+                        )
+                    }
+                    // Local items take precedence over the shared global ones
+                    Access::Ref | Access::Clone
+                        if shared.contains(&key)
+                            && !(*is_local && each.iter().any(|item| item.key == key)) =>
+                    {
+                        emit_error!(
+                            arg.decl,
+                            "{} is shared and must be requested with `#[shared]`",
+                            arg.describe()
+                        )
+                    }
+                    Access::Mut if !is_local => emit_error!(
+                        arg.decl,
+                        "`{}` cannot obtain mutable access to the state",
+                        fn_id;
+                        help = "use `#[shared]` for items returned from `before_all`"
+                    ),
+                    Access::Mut if missing => emit_error!(
+                        arg.decl,
+                        "{} is not provided to `{}` mutably",
+                        arg.describe(),
+                        fn_id;
+                        help = "return it from `before_each`, or use `#[shared]` for items returned from `before_all`"
+                    ),
+                    Access::Take if !is_local => emit_error!(
+                        arg.decl,
+                        "`{}` cannot take items from the state",
+                        fn_id;
+                        help = "use `#[clone]` to obtain an owned item"
+                    ),
+                    Access::Take if !arg.optional && missing => emit_error!(
+                        arg.decl,
+                        "{} cannot be taken by `{}`",
+                        arg.describe(),
+                        fn_id;
+                        help = "only items returned from `before_each` can be taken, use `#[clone]` for global items"
+                    ),
+                    Access::Shared if missing => {
+                        emit_error!(
+                            arg.decl,
+                            "{} is not provided to `{}`",
+                            arg.describe(),
+                            fn_id;
+                            help = "shared items must be returned from `before_all`"
+                        )
+                    }
+                    _ => {}
+                }
+
+                // Optional items are allowed to be missing
+                if let Lookup::Unresolved(candidates) = lookup {
+                    if !arg.optional {
+                        let candidates = candidates
+                            .iter()
+                            .map(|item| {
+                                let up = depth - item.depth + usize::from(*in_fixture);
+                                let supers = (0..up).map(|_| quote!(super::));
+                                let alias = &item.alias;
+                                quote!(#(#supers)* #alias)
+                            })
+                            .collect();
+                        checks.push(TypeCheck { arg: i, candidates });
+                    }
+                }
+            }
+            type_checks.push(checks);
+        }
+
+        for test in self.tests.iter() {
+            for arg in test.inputs.iter().map(StateArg::parse) {
+                let Some(read) = around.iter().find(|read| {
+                    arg.item() == read.item
+                        && arg.access != Access::Shared
+                        && read.access != Access::Shared
+                        && (arg.removes() || read.access == Access::Mut)
+                }) else {
+                    continue;
+                };
+                emit_error!(
+                    arg.decl,
+                    "{} is requested by both `{}` and `{}` wrapping it",
+                    arg.describe(),
+                    test.item.sig.ident,
+                    read.hook;
+                    help = REMOVED_HELP
+                )
+            }
+
+            for arg in test.inputs.iter().map(StateArg::parse) {
+                let Some(read) = after.iter().find(|read| {
+                    arg.item() == read.item
+                        && arg.access == Access::Take
+                        && read.access != Access::Shared
+                        && !read.optional
+                }) else {
+                    continue;
+                };
+                emit_error!(
+                    arg.decl,
+                    "{} is taken by `{}`, but `{}` running after it requests it",
+                    arg.describe(),
+                    test.item.sig.ident,
+                    read.hook;
+                    help = "taken items are no longer in the state, request it as `Option<T>` in `{}`", read.hook
+                )
+            }
+        }
+
+        // The readers are in the order of the hooks followed by the tests
+        let mut type_checks = type_checks.into_iter();
+        for hook in self
+            .before_all
+            .iter_mut()
+            .chain(self.before_each.iter_mut())
+            .chain(self.after_all.iter_mut())
+            .chain(self.teardown.iter_mut())
+            .chain(self.around_each.iter_mut())
+            .chain(self.after_each.iter_mut())
+            .chain(self.cleanup.iter_mut())
+        {
+            hook.type_checks = type_checks.next().unwrap_or_default();
+        }
+        for test in self.tests.iter_mut() {
+            test.type_checks = type_checks.next().unwrap_or_default();
+        }
+
+        for modifier in self
+            .before_all
+            .iter_mut()
+            .filter_map(|hook| hook.modifier.as_mut())
+        {
+            modifier.shared = modifier
+                .items()
+                .iter()
+                .map(|(ty, name)| {
+                    shared.contains(&(type_key(ty), name.as_ref().map(LitStr::value)))
+                })
+                .collect();
+        }
+
+        shared
+    }
+}
+
+/// The arguments of the `suite` macro, the suite identifier followed by the paths of
+/// the `fixtures` modules it uses, e.g. `#[suitest::suite(my_suite, use = crate::fixtures::db)]`.
+pub struct SuiteArgs {
+    pub id: Ident,
+
+    pub fixtures: Vec<syn::Path>,
+}
+
+impl Parse for SuiteArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let id = input.parse()?;
+        let mut fixtures = vec![];
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![use]>()?;
+            input.parse::<Token![=]>()?;
+            fixtures.push(input.call(syn::Path::parse_mod_style)?);
+        }
+        Ok(Self { id, fixtures })
+    }
+}
+
+/// The hooks of a `fixtures` module used by a suite.
+#[derive(Debug)]
+pub struct Fixture {
+    /// The path to the `fixtures` module, relative to the module of the suite
+    pub path: syn::Path,
+
+    /// The imports of the `fixtures` module, required by the hooks
+    pub imports: Vec<ItemUse>,
+
+    /// The hooks to process in the suite
+    pub hooks: Vec<ItemFn>,
+}
+
+impl Parse for Fixture {
+    /// Parses the fixture as passed between the macros, its path followed by its items in braces.
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.call(syn::Path::parse_mod_style)?;
+        let content;
+        braced!(content in input);
+        Self::parse_items(path, &content)
+    }
+}
+
+impl Fixture {
+    fn parse_items(path: syn::Path, input: ParseStream) -> syn::Result<Self> {
+        let (mut imports, mut hooks) = (vec![], vec![]);
+        while !input.is_empty() {
+            match input.parse()? {
+                Item::Use(item) => imports.push(item),
+                Item::Fn(item) => hooks.push(item),
+                item => return Err(syn::Error::new(item.span(), "unexpected fixture item")),
+            }
+        }
+        Ok(Self {
+            path,
+            imports,
+            hooks,
+        })
+    }
+}
+
+/// The input of the `__fixtures` macro, invoked by the `__suitest_fixtures` macro of a `fixtures` module.
+///
+/// Holds the items of the `fixtures` module, the suite identifier, the paths of the `fixtures`
+/// modules left to collect starting with the invoking one, the collected fixtures and the suite.
+pub struct UseFixtures {
+    pub id: Ident,
+
+    pub pending: Vec<syn::Path>,
+
+    pub fixtures: Vec<Fixture>,
+
+    pub item_mod: ItemMod,
+}
+
+impl Parse for UseFixtures {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let items;
+        braced!(items in input);
+        let id = input.parse()?;
+
+        let content;
+        bracketed!(content in input);
+        let mut pending = Punctuated::<syn::Path, Token![,]>::parse_terminated_with(
+            &content,
+            syn::Path::parse_mod_style,
+        )?
+        .into_iter()
+        .collect::<Vec<_>>();
+
+        let content;
+        bracketed!(content in input);
+        let mut fixtures = vec![];
+        while !content.is_empty() {
+            fixtures.push(content.parse()?);
+        }
+
+        if pending.is_empty() {
+            return Err(input.error("expected the path of the invoking fixtures"));
+        }
+        fixtures.push(Fixture::parse_items(pending.remove(0), &items)?);
+
+        Ok(Self {
+            id,
+            pending,
+            fixtures,
+            item_mod: input.parse()?,
         })
     }
 }
 
+impl ToTokens for Fixture {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let Self {
+            path,
+            imports,
+            hooks,
+        } = self;
+        tokens.extend(quote!(#path { #(#imports)* #(#hooks)* }))
+    }
+}
+
 /// Represents the leftover hook function after extracting the state getters and setters.
 #[derive(Debug)]
 pub struct SuiteFn {
@@ -218,125 +1013,561 @@ pub struct SuiteFn {
 
     /// The extracted state getters from the original fn
     pub inputs: Punctuated<FnArg, Comma>,
+
+    /// The duration after which the hook fails
+    pub timeout: Option<Duration>,
+
+    /// The position of the hook among the hooks of the same kind, lower runs first
+    pub order: i32,
+
+    /// The parameter receiving the context passed by the suite, e.g. the `SuiteOutcome`
+    /// of `teardown` hooks or the test wrapped by `around_each` hooks
+    pub context: Option<PatType>,
+
+    /// The parameter receiving the `TestContext` of `after_each` and `cleanup` hooks
+    pub test_context: Option<PatType>,
+
+    /// The index of the `fixtures` module declaring the hook, if it is not declared in the suite
+    pub fixture: Option<usize>,
+
+    /// The arguments that could not be matched with the provided items by name
+    pub type_checks: Vec<TypeCheck>,
 }
 
 impl SuiteFn {
-    fn new(item: ItemFn, inputs: Punctuated<FnArg, Comma>) -> Self {
+    /// The module generated for the hook if it is declared in a `fixtures` module.
+    pub fn fixture_module(&self) -> Option<Ident> {
+        self.fixture
+            .map(|index| format_ident!("__fixture{}_{}", index, self.item.sig.ident))
+    }
+
+    /// The identifiers of the aliases generated for the types of the returned items.
+    pub fn item_aliases(&self) -> Vec<Ident> {
+        let len = self.modifier.as_ref().map_or(0, |m| m.names.len());
+        (0..len)
+            .map(|i| format_ident!("__{}_item{}", self.item.sig.ident, i))
+            .collect()
+    }
+
+    fn new(
+        item: ItemFn,
+        inputs: Punctuated<FnArg, Comma>,
+        timeout: Option<Duration>,
+        order: i32,
+        modifier: Option<StateModifier>,
+    ) -> Self {
         Self {
             item,
-            modifier: None,
+            modifier,
             inputs,
+            timeout,
+            order,
+            context: None,
+            test_context: None,
+            fixture: None,
+            type_checks: vec![],
         }
     }
-
-    fn set_modifier(&mut self, modifier: StateModifier) {
-        self.modifier = Some(modifier);
-    }
 }
 
 /// A test function.
 #[derive(Debug)]
 pub struct TestFn {
-    /// The test identifier
-    pub id: usize,
-
-    /// The function item with its inputs stripped
+    /// The function item with its inputs and `case` attributes stripped
     pub item: ItemFn,
 
-    /// The stripped inputs
+    /// The stripped inputs that get obtained from the state
     pub inputs: Punctuated<FnArg, Comma>,
-}
 
-impl TestFn {
-    fn new(id: usize, item: ItemFn, inputs: Punctuated<FnArg, Comma>) -> Self {
-        Self { id, item, inputs }
-    }
-}
+    /// The leading inputs bound to the `case` arguments. These are kept in the signature
+    /// of the generated test.
+    pub params: Punctuated<FnArg, Comma>,
 
-/// Holds the expanded function definition, its ident and whether it is async.
-pub struct FnQuote {
-    /// The expanded definition containing state getters and setters
-    pub tokens: proc_macro2::TokenStream,
+    /// The invocations of the test. Contains a single case without arguments
+    /// if the test is not parameterized.
+    pub cases: Vec<TestCase>,
 
-    /// The ident of the function used to invoke it in quote!().
-    pub id: Ident,
+    /// Set if the test is annotated with `should_panic`
+    pub should_panic: Option<ShouldPanic>,
 
-    pub is_async: bool,
+    /// The `ignore` attribute of the test, if any
+    pub ignore: Option<Attribute>,
+
+    /// The duration after which the test fails, overrides the suite timeout
+    pub timeout: Option<Duration>,
+
+    /// The number of times to rerun the test if it fails, overrides the suite retries
+    pub retries: Option<usize>,
+
+    /// The arguments that could not be matched with the provided items by name
+    pub type_checks: Vec<TypeCheck>,
 }
 
-impl FnQuote {
-    pub fn new(tokens: proc_macro2::TokenStream, id: Ident, is_async: bool) -> Self {
-        Self {
-            tokens,
-            id,
-            is_async,
+impl TestFn {
+    /// Parse the `case` attributes of the test and assign a local state identifier to each.
+    fn new(
+        id: &mut usize,
+        mut item: ItemFn,
+        inputs: Punctuated<FnArg, Comma>,
+        timeout: Option<Duration>,
+    ) -> Self {
+        let case_args = item
+            .attrs
+            .iter()
+            .filter(|attr| is_attr(attr, CASE))
+            .map(|attr| {
+                attr.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+                    .unwrap_or_else(|e| abort!(e.span(), "invalid case arguments: {}", e))
+            })
+            .collect::<Vec<_>>();
+
+        item.attrs.retain(|attr| !is_attr(attr, CASE));
+
+        let should_panic = item
+            .attrs
+            .iter()
+            .find(|attr| is_attr(attr, SHOULD_PANIC))
+            .map(ShouldPanic::parse);
+
+        if should_panic.is_some() && !matches!(item.sig.output, ReturnType::Default) {
+            abort!(
+                item.sig.output.span(),
+                "functions using `#[should_panic]` must return `()`"
+            )
         }
-    }
 
-    // Invokes the function with `.await` or without it.
-    pub fn quote_invoke_task(&self, const_id: &Ident) -> proc_macro2::TokenStream {
-        let id = &self.id;
+        let ignore = item
+            .attrs
+            .iter()
+            .find(|attr| is_attr(attr, IGNORE))
+            .cloned();
 
-        if self.is_async {
-            quote!(#id ::<#const_id> ().await;)
-        } else {
-            quote!(#id ::<#const_id> ();)
+        let retries = item
+            .attrs
+            .iter()
+            .find(|attr| is_attr(attr, RETRY))
+            .map(|attr| {
+                attr.parse_args::<LitInt>()
+                    .and_then(|lit| lit.base10_parse::<usize>())
+                    .unwrap_or_else(|_| {
+                        abort!(
+                            attr.span(),
+                            "expected the number of retries, e.g. `#[retry(3)]`"
+                        )
+                    })
+            });
+
+        item.attrs.retain(|attr| {
+            !is_attr(attr, SHOULD_PANIC) && !is_attr(attr, IGNORE) && !is_attr(attr, RETRY)
+        });
+
+        let param_count = case_args.first().map(Punctuated::len).unwrap_or_default();
+
+        if let Some(args) = case_args.iter().find(|args| args.len() != param_count) {
+            abort!(
+                args.span(),
+                "all cases must have the same number of arguments, expected {}",
+                param_count
+            )
         }
-    }
 
-    /// Invokes the suite function either blocking on a tokio runtime
-    /// or by just invoking it.
-    pub fn quote_invoke_suite(&self) -> proc_macro2::TokenStream {
-        let id = &self.id;
-        if self.is_async {
-            quote!(rt.block_on(#id());)
+        if param_count > inputs.len() {
+            abort!(
+                item.sig.ident.span(),
+                "cases have {} arguments, but the test accepts only {}",
+                param_count,
+                inputs.len()
+            )
+        }
+
+        let mut inputs = inputs.into_iter();
+        let params = inputs.by_ref().take(param_count).collect();
+        let inputs = inputs.collect();
+
+        let cases = if case_args.is_empty() {
+            vec![TestCase::new(id, None, Punctuated::new())]
         } else {
-            quote!(#id();)
+            case_args
+                .into_iter()
+                .enumerate()
+                .map(|(i, args)| TestCase::new(id, Some(format_ident!("case_{}", i + 1)), args))
+                .collect()
+        };
+
+        Self {
+            item,
+            inputs,
+            params,
+            cases,
+            should_panic,
+            ignore,
+            timeout,
+            retries,
+            type_checks: vec![],
         }
     }
-}
 
-impl ToTokens for FnQuote {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        tokens.extend(self.tokens.clone())
+    /// The identifier of the generated test implementation. Differs from the original
+    /// so it does not clash with the libtest entries of `split` suites.
+    pub fn impl_id(&self) -> Ident {
+        format_ident!("__{}", self.item.sig.ident)
     }
 }
 
-/// The info needed to invoke a task and its cleanup if any
-pub struct TaskQuote {
-    /// The task variable
-    pub id: Ident,
-
-    /// The constant variable related to this task
-    pub const_id: Ident,
+/// The parsed `should_panic` attribute of a test.
+#[derive(Debug)]
+pub struct ShouldPanic {
+    /// The substring the panic message must contain
+    pub expected: Option<LitStr>,
+}
 
-    /// Whether or not to run cleanup if the task fails and
-    /// whether or not it is async
-    pub cleanup: Option<(Ident, bool)>,
+impl ShouldPanic {
+    /// Parse any of `#[should_panic]`, `#[should_panic = "..."]` and `#[should_panic(expected = "...")]`.
+    fn parse(attr: &Attribute) -> Self {
+        let expected = match attr.meta {
+            Meta::Path(_) => return Self { expected: None },
+            Meta::NameValue(ref nv) => nv.value.clone(),
+            Meta::List(ref list) => {
+                let arg = list.parse_args::<MetaNameValue>().unwrap_or_else(|e| {
+                    abort!(list.span(), "invalid should_panic argument: {}", e)
+                });
+                if !arg.path.is_ident(EXPECTED) {
+                    abort!(arg.path.span(), "expected `expected = \"...\"`")
+                }
+                arg.value
+            }
+        };
 
-    /// The name of the thread that will get spawned
-    pub fn_id: Ident,
-}
+        let Expr::Lit(ExprLit {
+            lit: Lit::Str(expected),
+            ..
+        }) = expected
+        else {
+            abort!(expected.span(), "expected message must be a string")
+        };
 
-impl TaskQuote {
-    pub fn new(id: Ident, fn_id: Ident, const_id: Ident, cleanup: Option<(Ident, bool)>) -> Self {
         Self {
-            id,
-            fn_id,
-            const_id,
-            cleanup,
+            expected: Some(expected),
         }
     }
 }
 
+/// A single invocation of a test function.
+#[derive(Debug)]
+pub struct TestCase {
+    /// The local state identifier
+    pub id: usize,
+
+    /// The case identifier, `None` if the test is not parameterized
+    pub ident: Option<Ident>,
+
+    /// The arguments bound positionally to the test parameters
+    pub args: Punctuated<Expr, Comma>,
+}
+
+impl TestCase {
+    fn new(id: &mut usize, ident: Option<Ident>, args: Punctuated<Expr, Comma>) -> Self {
+        let case = Self {
+            id: *id,
+            ident,
+            args,
+        };
+        *id += 1;
+        case
+    }
+
+    /// The name of the case used when reporting, e.g. `my_test::case_1`.
+    pub fn name(&self, test: &Ident) -> String {
+        match self.ident {
+            Some(ref ident) => format!("{test}::{ident}"),
+            None => test.to_string(),
+        }
+    }
+}
+
+/// Holds the expanded function definition, its ident and whether it is async.
+#[derive(Clone)]
+pub struct FnQuote {
+    /// The expanded definition containing state getters and setters
+    pub tokens: proc_macro2::TokenStream,
+
+    /// The ident of the function used to invoke it in quote!().
+    pub id: Ident,
+
+    /// The path to the module of the function, relative to where it is invoked
+    pub path: proc_macro2::TokenStream,
+
+    pub is_async: bool,
+
+    /// Whether the function returns a `Result`
+    pub is_fallible: bool,
+
+    /// The duration after which the function fails
+    pub timeout: Option<Duration>,
+
+    /// Whether the function takes the `outcome` of the suite
+    pub has_context: bool,
+
+    /// Whether the function takes a `#[shared]` item, which sync functions lock by blocking
+    pub locks_shared: bool,
+}
+
+impl FnQuote {
+    pub fn new(
+        tokens: proc_macro2::TokenStream,
+        id: Ident,
+        is_async: bool,
+        is_fallible: bool,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            tokens,
+            id,
+            path: quote!(),
+            is_async,
+            is_fallible,
+            timeout,
+            has_context: false,
+            locks_shared: false,
+        }
+    }
+
+    /// The function as invoked from a suite nested in the one declaring it.
+    /// The definition is left out since it is already in the parent module.
+    pub fn in_child(&self) -> Self {
+        let path = &self.path;
+        Self {
+            tokens: quote!(),
+            path: quote!(super:: #path),
+            ..self.clone()
+        }
+    }
+
+    /// The path used to invoke the function.
+    pub fn quote_path(&self) -> proc_macro2::TokenStream {
+        let (path, id) = (&self.path, &self.id);
+        quote!(#path #id)
+    }
+
+    // Invokes the function with `.await` or without it.
+    pub fn quote_invoke_task(&self, const_id: &Ident) -> proc_macro2::TokenStream {
+        let id = &self.quote_path();
+        let name = self.id.to_string();
+
+        let invoke = match (self.is_async, self.timeout) {
+            (true, None) => quote!(#id ::<#const_id> ().await),
+            (false, None) => quote!(#id ::<#const_id> ()),
+            (true, Some(timeout)) => {
+                let timeout = quote_duration(timeout);
+                quote!(suitest::internal::timeout(#timeout, #name, #id ::<#const_id> ()).await)
+            }
+            (false, Some(timeout)) => {
+                let timeout = quote_duration(timeout);
+                quote!(suitest::internal::run_timed(#name, #timeout, #id ::<#const_id>))
+            }
+        };
+
+        self.quote_unwrap(invoke)
+    }
+
+    /// Invokes the suite function either blocking on a tokio runtime
+    /// or by just invoking it.
+    pub fn quote_invoke_suite(&self) -> proc_macro2::TokenStream {
+        let id = &self.quote_path();
+        let name = self.id.to_string();
+
+        let context = self.has_context.then_some(quote!(&outcome));
+
+        let invoke = match (self.is_async, self.timeout) {
+            (true, None) => quote!(rt.block_on(#id(#context))),
+            (false, None) => quote!(#id(#context)),
+            (true, Some(timeout)) => {
+                let timeout = quote_duration(timeout);
+                quote!(rt.block_on(suitest::internal::timeout(#timeout, #name, #id(#context))))
+            }
+            (false, Some(timeout)) if self.has_context => {
+                let timeout = quote_duration(timeout);
+                quote!(suitest::internal::run_timed(#name, #timeout, {
+                    let outcome = outcome.clone();
+                    move || #id(&outcome)
+                }))
+            }
+            (false, Some(timeout)) => {
+                let timeout = quote_duration(timeout);
+                quote!(suitest::internal::run_timed(#name, #timeout, #id))
+            }
+        };
+
+        self.quote_unwrap(invoke)
+    }
+
+    /// Fails with the error's debug output if the function is fallible.
+    fn quote_unwrap(&self, invoke: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if self.is_fallible {
+            let id = self.id.to_string();
+            quote!(suitest::internal::unwrap_result(#invoke, #id);)
+        } else {
+            quote!(#invoke;)
+        }
+    }
+}
+
+impl ToTokens for FnQuote {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        tokens.extend(self.tokens.clone())
+    }
+}
+
+/// The info needed to invoke a task and its cleanup if any
+pub struct TaskQuote {
+    /// The task variable
+    pub id: Ident,
+
+    /// The constant variable related to this task
+    pub const_id: Ident,
+
+    /// The paths to the cleanup hooks to run if the task fails and
+    /// whether or not they are async
+    pub cleanup: Vec<(proc_macro2::TokenStream, bool)>,
+
+    /// The name of the test used for reporting and for the thread that will get spawned
+    pub name: String,
+
+    /// Whether the test is annotated with `ignore`
+    pub ignored: bool,
+
+    /// The duration after which the task fails
+    pub timeout: Option<Duration>,
+
+    /// Whether the panic of the task is recorded for the `TestContext` of its hooks
+    pub track: bool,
+}
+
+impl TaskQuote {
+    pub fn new(
+        id: Ident,
+        name: String,
+        const_id: Ident,
+        cleanup: Vec<(proc_macro2::TokenStream, bool)>,
+        ignored: bool,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            const_id,
+            cleanup,
+            ignored,
+            timeout,
+            track: false,
+        }
+    }
+
+    /// The async task, wrapped with its deadline if it has a timeout.
+    pub fn quote_future(&self) -> proc_macro2::TokenStream {
+        let (id, name) = (&self.id, &self.name);
+        match self.timeout {
+            Some(timeout) => {
+                let timeout = quote_duration(timeout);
+                quote!(suitest::internal::timeout(#timeout, #name, #id))
+            }
+            None => quote!(#id),
+        }
+    }
+
+    /// Spawns the sync task on a thread which times out if the task has a timeout.
+    pub fn quote_spawn(&self) -> proc_macro2::TokenStream {
+        let (id, name, timeout) = (&self.id, &self.name, self.quote_timeout());
+        quote!(suitest::internal::spawn_timed(#name, #timeout, #id))
+    }
+
+    /// The task timeout as an `Option<Duration>`.
+    pub fn quote_timeout(&self) -> proc_macro2::TokenStream {
+        match self.timeout {
+            Some(timeout) => {
+                let timeout = quote_duration(timeout);
+                quote!(::std::option::Option::Some(#timeout))
+            }
+            None => quote!(::std::option::Option::None),
+        }
+    }
+
+    /// Runs the sync task on the current thread and catches its panic. Tasks with
+    /// a timeout are run on a separate thread so the deadline can be enforced.
+    pub fn quote_catch_unwind(&self) -> proc_macro2::TokenStream {
+        let id = &self.id;
+        if self.timeout.is_some() {
+            let spawn = self.quote_spawn();
+            quote!(#spawn.join())
+        } else {
+            quote!(::std::panic::catch_unwind(#id))
+        }
+    }
+
+    /// Invokes the cleanup hooks for this task in reverse order, if any.
+    /// The `payload` is the `&(dyn Any + Send)` the task panicked with.
+    pub fn quote_cleanup(
+        &self,
+        payload: proc_macro2::TokenStream,
+    ) -> Option<proc_macro2::TokenStream> {
+        let const_id = &self.const_id;
+        let fail = self
+            .track
+            .then(|| quote!(__CONTEXT[#const_id].fail(#payload);));
+        let cleanup = self.cleanup.iter().rev().map(|(cleanup, is_async)| {
+            if *is_async {
+                quote!(rt.block_on(#cleanup::<#const_id>());)
+            } else {
+                quote!(#cleanup::<#const_id>();)
+            }
+        });
+        (!self.cleanup.is_empty()).then(|| quote!(#fail #(#cleanup)*))
+    }
+}
+
 /// Configuration for the test suite.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct SuiteConfig {
     /// If true, the test suite prints all generated actions
     pub verbose: bool,
 
     /// If true, the test suite executes tests one by one
     pub sequential: bool,
+
+    /// If true, each test is registered as a separate libtest test
+    pub split: bool,
+
+    /// The duration after which tests fail, unless specified on the test
+    pub timeout: Option<Duration>,
+
+    /// The number of times to rerun failing tests, unless specified on the test
+    pub retries: usize,
+
+    /// If true, no more tests are executed after the first failure
+    pub fail_fast: bool,
+
+    /// The maximum number of tests running at the same time, unbounded if `None`
+    pub max_concurrency: Option<usize>,
+
+    /// The tokio runtime used for async hooks and tests
+    pub runtime: SuiteRuntime,
+
+    /// The number of worker threads of the multi threaded runtime
+    pub worker_threads: Option<usize>,
+
+    /// If true, the runtime starts with its clock paused
+    pub start_paused: bool,
+}
+
+/// The flavor of the tokio runtime used by the suite.
+#[derive(Debug, Default, Clone)]
+pub enum SuiteRuntime {
+    #[default]
+    CurrentThread,
+    MultiThread,
+
+    /// A user provided `fn() -> tokio::runtime::Runtime`
+    Custom(ExprPath),
 }
 
 impl SuiteConfig {
@@ -385,47 +1616,947 @@ impl SuiteConfig {
                             };
                             config.sequential = bool.value();
                         }
+                        SPLIT => {
+                            let Expr::Lit(ExprLit {
+                                lit: Lit::Bool(bool),
+                                ..
+                            }) = arg.value
+                            else {
+                                abort!(arg.value, "split flag must be a boolean")
+                            };
+                            config.split = bool.value();
+                        }
+                        TIMEOUT => {
+                            let Expr::Lit(ExprLit {
+                                lit: Lit::Str(ref timeout),
+                                ..
+                            }) = arg.value
+                            else {
+                                abort!(arg.value, "timeout must be a string, e.g. \"30s\"")
+                            };
+                            config.timeout = Some(parse_duration(timeout));
+                        }
+                        RETRIES => {
+                            let Expr::Lit(ExprLit {
+                                lit: Lit::Int(ref retries),
+                                ..
+                            }) = arg.value
+                            else {
+                                abort!(arg.value, "retries must be an integer")
+                            };
+                            config.retries = retries
+                                .base10_parse()
+                                .unwrap_or_else(|e| abort!(retries.span(), e));
+                        }
+                        FAIL_FAST => {
+                            let Expr::Lit(ExprLit {
+                                lit: Lit::Bool(bool),
+                                ..
+                            }) = arg.value
+                            else {
+                                abort!(arg.value, "fail_fast flag must be a boolean")
+                            };
+                            config.fail_fast = bool.value();
+                        }
+                        MAX_CONCURRENCY => {
+                            let Expr::Lit(ExprLit {
+                                lit: Lit::Int(ref max),
+                                ..
+                            }) = arg.value
+                            else {
+                                abort!(arg.value, "max_concurrency must be an integer")
+                            };
+                            let max = max.base10_parse().unwrap_or_else(|e| abort!(max.span(), e));
+                            if max == 0 {
+                                abort!(arg.value, "max_concurrency must be greater than 0")
+                            }
+                            config.max_concurrency = Some(max);
+                        }
+                        RUNTIME => match arg.value {
+                            Expr::Lit(ExprLit {
+                                lit: Lit::Str(ref flavor),
+                                ..
+                            }) => {
+                                config.runtime = match flavor.value().as_str() {
+                                    "current_thread" => SuiteRuntime::CurrentThread,
+                                    "multi_thread" => SuiteRuntime::MultiThread,
+                                    _ => abort!(
+                                        flavor.span(),
+                                        "runtime must be either \"current_thread\" or \"multi_thread\""
+                                    ),
+                                };
+                            }
+                            Expr::Path(builder) => config.runtime = SuiteRuntime::Custom(builder),
+                            _ => abort!(
+                                arg.value,
+                                "runtime must be a flavor or a path to a fn returning a runtime"
+                            ),
+                        },
+                        WORKER_THREADS => {
+                            let Expr::Lit(ExprLit {
+                                lit: Lit::Int(ref threads),
+                                ..
+                            }) = arg.value
+                            else {
+                                abort!(arg.value, "worker_threads must be an integer")
+                            };
+                            let threads = threads
+                                .base10_parse()
+                                .unwrap_or_else(|e| abort!(threads.span(), e));
+                            if threads == 0 {
+                                abort!(arg.value, "worker_threads must be greater than 0")
+                            }
+                            config.worker_threads = Some(threads);
+                        }
+                        START_PAUSED => {
+                            let Expr::Lit(ExprLit {
+                                lit: Lit::Bool(bool),
+                                ..
+                            }) = arg.value
+                            else {
+                                abort!(arg.value, "start_paused flag must be a boolean")
+                            };
+                            config.start_paused = bool.value();
+                        }
 
                         _ => abort!(arg.span(), "unrecognised argument"),
                     }
                 }
+
+                // libtest schedules the tests of split suites
+                if config.split && config.fail_fast {
+                    abort!(meta_list.span(), "fail_fast cannot be used in split suites")
+                }
+                match config.runtime {
+                    SuiteRuntime::CurrentThread if config.worker_threads.is_some() => abort!(
+                        meta_list.span(),
+                        "worker_threads can only be used with the \"multi_thread\" runtime"
+                    ),
+                    SuiteRuntime::MultiThread if config.start_paused => abort!(
+                        meta_list.span(),
+                        "start_paused can only be used with the \"current_thread\" runtime"
+                    ),
+                    SuiteRuntime::Custom(_)
+                        if config.worker_threads.is_some() || config.start_paused =>
+                    {
+                        abort!(
+                            meta_list.span(),
+                            "worker_threads and start_paused cannot be used with a custom runtime"
+                        )
+                    }
+                    _ => {}
+                }
+                if config.split && config.max_concurrency.is_some() {
+                    abort!(
+                        meta_list.span(),
+                        "max_concurrency cannot be used in split suites, use `--test-threads` instead"
+                    )
+                }
+                if config.sequential && config.max_concurrency.is_some() {
+                    abort!(
+                        meta_list.span(),
+                        "max_concurrency cannot be used in sequential suites, which run one test at a time"
+                    )
+                }
             }
         }
 
         config
     }
+
+    /// The expression building the tokio runtime of the suite.
+    pub fn quote_runtime(&self) -> proc_macro2::TokenStream {
+        let builder = match self.runtime {
+            SuiteRuntime::Custom(ref builder) => return quote!(#builder()),
+            SuiteRuntime::CurrentThread => quote!(tokio::runtime::Builder::new_current_thread()),
+            SuiteRuntime::MultiThread => quote!(tokio::runtime::Builder::new_multi_thread()),
+        };
+
+        let worker_threads = self
+            .worker_threads
+            .map(|threads| quote!(.worker_threads(#threads)));
+        let start_paused = self.start_paused.then(|| quote!(.start_paused(true)));
+
+        quote!(#builder
+            .enable_all()
+            #worker_threads
+            #start_paused
+            .build()
+            .expect("couldn't build runtime"))
+    }
 }
 
 /// The accepted values found at the function signature
 #[derive(Debug)]
-pub enum PathOrTupleReturn {
-    Path(TypePath),
-    Tuple(TypeTuple),
-}
+pub enum HookOutput {
+    /// A single item inserted to the state.
+    Single(Type),
 
-/// The accepted values found at the end of a function block
-#[derive(Debug)]
-pub enum PathOrTupleExpr {
-    Path(ExprPath),
-    Tuple(ExprTuple),
+    /// Multiple items, each inserted to the state.
+    Tuple(TypeTuple),
 }
 
 /// An intermediary repr of a suite function that should modify the test suite state
 #[derive(Debug)]
 pub struct StateModifier {
     /// The tuple or path from the function return value that gets used to insert the corresponding type to the state.
-    pub fn_output: PathOrTupleReturn,
+    pub fn_output: HookOutput,
+
+    /// The original return type of the function, computed by its block.
+    pub output: Type,
 
-    /// The tuple or path from the function block that gets used to insert the corresponding type to the state.
-    /// The type must correspond to `fn_output`.
-    pub last_block_item: PathOrTupleExpr,
+    /// The names the items are inserted under, one for each type in `fn_output`.
+    pub names: Vec<Option<LitStr>>,
+
+    /// Whether the items are requested with `#[shared]` and must be inserted behind a lock,
+    /// one for each type in `fn_output`.
+    pub shared: Vec<bool>,
 }
 
 impl StateModifier {
-    pub fn span(&self) -> proc_macro2::Span {
-        match self.last_block_item {
-            PathOrTupleExpr::Path(ref p) => p.span(),
-            PathOrTupleExpr::Tuple(ref t) => t.span(),
+    /// The types inserted to the state along with their names.
+    pub fn items(&self) -> Vec<(Type, Option<LitStr>)> {
+        let types = match self.fn_output {
+            HookOutput::Single(ref ty) => vec![ty.clone()],
+            HookOutput::Tuple(ref t) => t.elems.iter().cloned().collect(),
+        };
+        types.into_iter().zip(self.names.iter().cloned()).collect()
+    }
+}
+
+/// Remove the parameter receiving the `SuiteOutcome` from the inputs of a `teardown` hook.
+fn take_outcome(inputs: Punctuated<FnArg, Comma>) -> (Punctuated<FnArg, Comma>, Option<PatType>) {
+    let (inputs, outcome) = take_context(inputs, "SuiteOutcome");
+    if let Some(ref outcome) = outcome {
+        if !matches!(*outcome.ty, Type::Reference(_)) {
+            abort!(
+                outcome.ty.span(),
+                "the suite outcome must be taken by reference";
+                help = "use `&SuiteOutcome`"
+            )
+        }
+    }
+    (inputs, outcome)
+}
+
+/// Remove the parameter of the given context type, taken by value or by reference,
+/// from the inputs of a hook.
+fn take_context(
+    inputs: Punctuated<FnArg, Comma>,
+    context: &str,
+) -> (Punctuated<FnArg, Comma>, Option<PatType>) {
+    let mut found = None;
+
+    let inputs = inputs
+        .into_iter()
+        .filter(|arg| {
+            let FnArg::Typed(pt) = arg else {
+                return true;
+            };
+
+            let ty = match pt.ty.as_ref() {
+                Type::Reference(r) if r.mutability.is_none() => r.elem.as_ref(),
+                ty => ty,
+            };
+            if type_key(ty) != context {
+                return true;
+            }
+
+            if found.replace(pt.clone()).is_some() {
+                abort!(pt.span(), "`{}` can only be requested once", context)
+            }
+            false
+        })
+        .collect();
+
+    (inputs, found)
+}
+
+/// Remove the parameter receiving the wrapped test from the inputs of an `around_each` hook.
+/// The test is the only `impl Trait` parameter, a future for async hooks and a closure for sync ones.
+fn take_wrapped_test(
+    item: &ItemFn,
+    inputs: Punctuated<FnArg, Comma>,
+) -> (Punctuated<FnArg, Comma>, PatType) {
+    let (tests, inputs): (Vec<_>, Vec<_>) = inputs
+        .into_iter()
+        .partition(|arg| matches!(arg, FnArg::Typed(pt) if matches!(*pt.ty, Type::ImplTrait(_))));
+
+    let help = if item.sig.asyncness.is_some() {
+        "add a `test: impl Future<Output = ()>` parameter and await it"
+    } else {
+        "add a `test: impl FnOnce()` parameter and call it"
+    };
+
+    let mut tests = tests.into_iter().map(|arg| match arg {
+        FnArg::Typed(pt) => pt,
+        FnArg::Receiver(_) => unreachable!(),
+    });
+
+    let Some(test) = tests.next() else {
+        abort!(
+            item.sig.ident.span(),
+            "around_each hooks must take the test as a parameter";
+            help = help
+        )
+    };
+    if let Some(other) = tests.next() {
+        abort!(
+            other.span(),
+            "around_each hooks can only take a single `impl Trait` parameter";
+            help = help
+        )
+    }
+
+    (inputs.into_iter().collect(), test)
+}
+
+/// Parse the `order = N` argument of a hook attribute, defaulting to 0.
+fn parse_order(attr: &Attribute) -> i32 {
+    let Meta::List(_) = attr.meta else {
+        return 0;
+    };
+
+    let arg = attr
+        .parse_args::<MetaNameValue>()
+        .unwrap_or_else(|e| abort!(e.span(), "invalid hook arguments: {}", e));
+
+    if !arg.path.is_ident("order") {
+        abort!(arg.path.span(), "unknown hook argument, expected `order`")
+    }
+
+    let (negative, lit) = match arg.value {
+        Expr::Unary(ExprUnary {
+            op: UnOp::Neg(_),
+            ref expr,
+            ..
+        }) => (true, expr.as_ref()),
+        ref expr => (false, expr),
+    };
+
+    let Expr::Lit(ExprLit {
+        lit: Lit::Int(ref order),
+        ..
+    }) = lit
+    else {
+        abort!(arg.value.span(), "`order` must be an integer literal")
+    };
+
+    let order = order
+        .base10_parse::<i32>()
+        .unwrap_or_else(|e| abort!(order.span(), "invalid order: {}", e));
+
+    if negative {
+        -order
+    } else {
+        order
+    }
+}
+
+/// Parse `#[name("a", _, "b")]`, naming each of the `count` items returned from a hook.
+/// Items named `_` are inserted without a name.
+fn parse_names(attr: &Attribute, count: usize) -> Vec<Option<LitStr>> {
+    let names = attr
+        .parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+        .unwrap_or_else(|e| abort!(e.span(), "invalid names: {}", e));
+
+    if names.len() != count {
+        abort!(
+            attr.span(),
+            "expected {} names, one for each returned item, found {}",
+            count,
+            names.len()
+        )
+    }
+
+    names
+        .into_iter()
+        .map(|name| match name {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(name),
+                ..
+            }) => Some(name),
+            Expr::Infer(_) => None,
+            name => abort!(name.span(), "names must be string literals or `_`"),
+        })
+        .collect()
+}
+
+/// How an argument is obtained from the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// `item: T`, a shared reference to the item
+    Ref,
+
+    /// `item: &mut T`, exclusive access to an item of the local state
+    Mut,
+
+    /// `#[shared] item: T`, exclusive access to an item of the global state through a lock
+    Shared,
+
+    /// `#[clone] item: T`, a clone of the item
+    Clone,
+
+    /// `#[take] item: T`, the item moved out of the local state
+    Take,
+}
+
+/// An argument of a hook or test obtained from the state.
+#[derive(Debug)]
+pub struct StateArg {
+    /// The binding of the argument
+    pub pat: Pat,
+
+    /// The type of the argument as declared in the signature
+    pub decl: Type,
+
+    /// The type of the item in the state
+    pub ty: Type,
+
+    /// The name of the item, given with `#[state("name")]`
+    pub name: Option<LitStr>,
+
+    pub access: Access,
+
+    /// Set if the argument is an `Option<T>`, obtained as `None` if the item is not in the state
+    pub optional: bool,
+}
+
+impl StateArg {
+    pub fn parse(arg: &FnArg) -> Self {
+        let FnArg::Typed(pt) = arg else {
+            abort!(arg.span(), "suitest functions cannot take in `self`")
+        };
+
+        let modes = [
+            (SHARED, Access::Shared),
+            (CLONE, Access::Clone),
+            (TAKE, Access::Take),
+        ]
+        .into_iter()
+        .filter(|(mode, _)| pt.attrs.iter().any(|attr| is_attr(attr, mode)))
+        .collect::<Vec<_>>();
+
+        if modes.len() > 1 {
+            abort!(
+                pt.span(),
+                "only one of `#[shared]`, `#[clone]` and `#[take]` can be used on an argument"
+            )
+        }
+
+        let (access, ty) = match (pt.ty.as_ref(), modes.first()) {
+            (Type::Reference(r), None) if r.mutability.is_some() => {
+                (Access::Mut, r.elem.as_ref().clone())
+            }
+            // Items are obtained as references, so `&T` is the same as `T`
+            (Type::Reference(r), None) => (Access::Ref, r.elem.as_ref().clone()),
+            (Type::Reference(_), Some((mode, _))) => abort!(
+                pt.ty.span(),
+                "`#[{}]` items are declared as owned values, e.g. `#[{}] item: T`",
+                mode,
+                mode
+            ),
+            (ty, Some((_, access))) => (*access, ty.clone()),
+            (ty, None) => (Access::Ref, ty.clone()),
+        };
+
+        let (ty, optional) = match split_option(&ty) {
+            Some(Type::Reference(r)) if access == Access::Ref && r.mutability.is_none() => {
+                (r.elem.as_ref().clone(), true)
+            }
+            Some(inner) if access != Access::Mut && access != Access::Shared => (inner, true),
+            Some(_) => abort!(pt.ty.span(), "shared and mutable items cannot be optional"),
+            None => (ty, false),
+        };
+
+        verify_static(&ty);
+
+        Self {
+            pat: pt.pat.as_ref().clone(),
+            decl: pt.ty.as_ref().clone(),
+            ty,
+            name: state_name(pt),
+            access,
+            optional,
+        }
+    }
+
+    /// Used to match the argument with the items returned from the hooks.
+    pub fn key(&self) -> StateKey {
+        (type_key(&self.ty), self.name.as_ref().map(LitStr::value))
+    }
+
+    /// Identifies the item by the spelling of its type. Unlike [StateArg::key], types with
+    /// the same name declared in different modules are told apart.
+    fn item(&self) -> StateKey {
+        (
+            type_display(&self.ty),
+            self.name.as_ref().map(LitStr::value),
+        )
+    }
+
+    /// Whether the item is removed from the state while the argument is in use.
+    fn removes(&self) -> bool {
+        matches!(self.access, Access::Mut | Access::Take)
+    }
+
+    /// Whether the two arguments of the same function request the same item while one
+    /// of them removes it from the state. Shared items are global and stored behind a lock,
+    /// so they never conflict with the local ones.
+    fn conflicts(&self, other: &StateArg) -> bool {
+        self.item() == other.item()
+            && self.access != Access::Shared
+            && other.access != Access::Shared
+            && (self.removes() || other.removes())
+    }
+
+    /// Look up the argument among the `provided` items. Only the spelling of the types can be
+    /// compared, so an item spelled differently can still be the same type.
+    fn lookup<'a>(&self, provided: &'a [Provided]) -> Lookup<'a> {
+        let name = self.name.as_ref().map(LitStr::value);
+        let ty = type_display(&self.ty);
+        let candidates = provided
+            .iter()
+            .filter(|item| item.key.1 == name)
+            .collect::<Vec<_>>();
+
+        if candidates.iter().any(|item| item.ty == ty) {
+            Lookup::Found
+        } else if candidates.is_empty() {
+            Lookup::Missing
+        } else {
+            Lookup::Unresolved(candidates)
+        }
+    }
+
+    /// The item description used in errors.
+    fn describe(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("`{}` named \"{}\"", type_display(&self.ty), name.value()),
+            None => format!("`{}`", type_display(&self.ty)),
         }
     }
 }
+
+/// Returns the name given to the argument with `#[state("name")]`, if any.
+pub fn state_name(arg: &PatType) -> Option<LitStr> {
+    let attr = arg.attrs.iter().find(|attr| is_attr(attr, STATE))?;
+    Some(attr.parse_args::<LitStr>().unwrap_or_else(|_| {
+        abort!(
+            attr.span(),
+            "expected the name of the item, e.g. `#[state(\"primary\")]`"
+        )
+    }))
+}
+
+/// Identifies an item in the state by its type and name.
+pub type StateKey = (String, Option<String>);
+
+/// An item returned from a hook, as seen by the functions reading from the state.
+#[derive(Clone)]
+pub struct Provided {
+    key: StateKey,
+
+    /// The type as spelled in the signature of the hook
+    ty: String,
+
+    /// The depth of the suite declaring the hook
+    depth: usize,
+
+    /// The path to the alias of the type from the module of that suite
+    alias: proc_macro2::TokenStream,
+}
+
+/// What a nested suite sees of the state of the suites it is declared in.
+#[derive(Default)]
+pub struct InheritedState {
+    /// The items returned from the `before_all` hooks
+    global: Vec<Provided>,
+
+    /// The items returned from the `before_each` hooks
+    each: Vec<Provided>,
+
+    /// The items read by the `around_each` hooks wrapping the tests
+    around: Vec<HookRead>,
+
+    /// The items read by the `after_each` and `cleanup` hooks running after the tests
+    after: Vec<HookRead>,
+}
+
+/// An item requested by a hook running along with each test, e.g. an `around_each` hook
+/// holding on to it while the test it wraps runs.
+#[derive(Clone)]
+pub struct HookRead {
+    hook: Ident,
+    item: StateKey,
+    access: Access,
+    optional: bool,
+}
+
+/// The help given for conflicting requests of an item.
+const REMOVED_HELP: &str =
+    "items requested with `&mut T` or `#[take]` are removed from the state while in use";
+
+/// How an argument is matched with the items provided to its function.
+enum Lookup<'a> {
+    /// An item with the same name is spelled the same way
+    Found,
+
+    /// No item has the same name, so none of them can match
+    Missing,
+
+    /// The items with the same name, which only rustc can tell apart from the argument
+    Unresolved(Vec<&'a Provided>),
+}
+
+/// Asserts that the type of an argument is one of the provided types once rustc resolves them,
+/// for arguments that cannot be matched by name, e.g. because of type aliases.
+#[derive(Debug)]
+pub struct TypeCheck {
+    /// The position of the argument in the inputs
+    pub arg: usize,
+
+    /// The paths to the aliases of the candidate types from the function reading the argument
+    pub candidates: Vec<proc_macro2::TokenStream>,
+}
+
+/// A string used to compare types by name. Paths are reduced to their last segment so
+/// `std::string::String` and `String` are considered the same type.
+fn type_key(ty: &Type) -> String {
+    match ty {
+        Type::Group(g) => type_key(&g.elem),
+        Type::Paren(p) => type_key(&p.elem),
+        Type::Path(p) if p.qself.is_none() => {
+            let Some(seg) = p.path.segments.last() else {
+                return String::new();
+            };
+            let PathArguments::AngleBracketed(ref args) = seg.arguments else {
+                return seg.ident.to_string();
+            };
+            let args = args
+                .args
+                .iter()
+                .map(|arg| match arg {
+                    GenericArgument::Type(ty) => type_key(ty),
+                    arg => arg.to_token_stream().to_string(),
+                })
+                .collect::<Vec<_>>();
+            format!("{}<{}>", seg.ident, args.join(","))
+        }
+        Type::Reference(r) => {
+            let mutability = if r.mutability.is_some() { "mut " } else { "" };
+            format!("&{mutability}{}", type_key(&r.elem))
+        }
+        Type::Slice(s) => format!("[{}]", type_key(&s.elem)),
+        Type::Array(a) => format!("[{};{}]", type_key(&a.elem), a.len.to_token_stream()),
+        Type::Tuple(t) => {
+            let elems = t.elems.iter().map(type_key).collect::<Vec<_>>();
+            format!("({})", elems.join(","))
+        }
+        ty => ty.to_token_stream().to_string(),
+    }
+}
+
+/// Abort if the type borrows anything, since items in the state must be `'static`.
+fn verify_static(ty: &Type) {
+    let verify_bounds = |bounds: &Punctuated<TypeParamBound, Token![+]>| {
+        for bound in bounds {
+            match bound {
+                TypeParamBound::Lifetime(lifetime) => verify_lifetime(lifetime),
+                TypeParamBound::Trait(bound) => verify_path(&bound.path),
+                _ => {}
+            }
+        }
+    };
+
+    match ty {
+        Type::Array(a) => verify_static(&a.elem),
+        Type::Group(g) => verify_static(&g.elem),
+        Type::Paren(p) => verify_static(&p.elem),
+        Type::Slice(s) => verify_static(&s.elem),
+        Type::Ptr(p) => verify_static(&p.elem),
+        Type::Tuple(t) => t.elems.iter().for_each(verify_static),
+        Type::Path(p) => {
+            if let Some(ref qself) = p.qself {
+                verify_static(&qself.ty);
+            }
+            verify_path(&p.path);
+        }
+        Type::Reference(r) => {
+            match r.lifetime {
+                Some(ref lifetime) => verify_lifetime(lifetime),
+                None => abort!(
+                    r.span(),
+                    "items in the state must be `'static`, found a reference without a lifetime";
+                    help = "store an owned value or a `&'static` reference instead"
+                ),
+            }
+            verify_static(&r.elem);
+        }
+        Type::TraitObject(t) => verify_bounds(&t.bounds),
+        Type::ImplTrait(t) => verify_bounds(&t.bounds),
+        // Function pointers only borrow for the duration of the call
+        _ => {}
+    }
+}
+
+/// Abort if the lifetime is not `'static`.
+fn verify_lifetime(lifetime: &Lifetime) {
+    if lifetime.ident != "static" {
+        abort!(
+            lifetime.span(),
+            "items in the state must be `'static`, found lifetime `{}`",
+            lifetime;
+            help = "store an owned value instead, e.g. `String` instead of `&str`"
+        )
+    }
+}
+
+/// Verify the generic arguments of a path are `'static`.
+fn verify_path(path: &syn::Path) {
+    for seg in path.segments.iter() {
+        match seg.arguments {
+            PathArguments::AngleBracketed(ref args) => {
+                for arg in args.args.iter() {
+                    match arg {
+                        GenericArgument::Type(ty) => verify_static(ty),
+                        GenericArgument::AssocType(assoc) => verify_static(&assoc.ty),
+                        GenericArgument::Lifetime(lifetime) => verify_lifetime(lifetime),
+                        _ => {}
+                    }
+                }
+            }
+            PathArguments::Parenthesized(_) | PathArguments::None => {}
+        }
+    }
+}
+
+/// Renders the type as it is written, used in the messages of the generated code.
+pub fn type_display(ty: &Type) -> String {
+    let bounds = |bounds: &Punctuated<TypeParamBound, Token![+]>| {
+        bounds
+            .iter()
+            .map(|bound| match bound {
+                TypeParamBound::Trait(bound) => path_display(&bound.path),
+                bound => bound.to_token_stream().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" + ")
+    };
+
+    match ty {
+        Type::Array(arr) => format!(
+            "[{}; {}]",
+            type_display(&arr.elem),
+            arr.len.to_token_stream()
+        ),
+        Type::Group(g) => type_display(&g.elem),
+        Type::Paren(p) => format!("({})", type_display(&p.elem)),
+        Type::Path(p) if p.qself.is_none() => path_display(&p.path),
+        Type::Ptr(p) => {
+            let mutability = if p.mutability.is_some() {
+                "mut"
+            } else {
+                "const"
+            };
+            format!("*{mutability} {}", type_display(&p.elem))
+        }
+        Type::Reference(r) => {
+            let lifetime = r
+                .lifetime
+                .as_ref()
+                .map(|l| format!("{l} "))
+                .unwrap_or_default();
+            let mutability = if r.mutability.is_some() { "mut " } else { "" };
+            format!("&{lifetime}{mutability}{}", type_display(&r.elem))
+        }
+        Type::Slice(s) => format!("[{}]", type_display(&s.elem)),
+        Type::Tuple(t) => {
+            let elems = t.elems.iter().map(type_display).collect::<Vec<_>>();
+            match elems.as_slice() {
+                [elem] => format!("({elem},)"),
+                elems => format!("({})", elems.join(", ")),
+            }
+        }
+        Type::TraitObject(t) => format!("dyn {}", bounds(&t.bounds)),
+        Type::ImplTrait(t) => format!("impl {}", bounds(&t.bounds)),
+        Type::BareFn(f) => {
+            let inputs = f
+                .inputs
+                .iter()
+                .map(|arg| type_display(&arg.ty))
+                .collect::<Vec<_>>();
+            format!("fn({}){}", inputs.join(", "), output_display(&f.output))
+        }
+        Type::Never(_) => String::from("!"),
+        ty => ty.to_token_stream().to_string(),
+    }
+}
+
+/// Renders the path along with its generic arguments.
+pub fn path_display(path: &syn::Path) -> String {
+    let segments = path
+        .segments
+        .iter()
+        .map(|seg| match seg.arguments {
+            PathArguments::None => seg.ident.to_string(),
+            PathArguments::AngleBracketed(ref args) => {
+                let args = args
+                    .args
+                    .iter()
+                    .map(|arg| match arg {
+                        GenericArgument::Type(ty) => type_display(ty),
+                        GenericArgument::AssocType(assoc) => {
+                            format!("{} = {}", assoc.ident, type_display(&assoc.ty))
+                        }
+                        arg => arg.to_token_stream().to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                format!("{}<{}>", seg.ident, args.join(", "))
+            }
+            PathArguments::Parenthesized(ref args) => {
+                let inputs = args.inputs.iter().map(type_display).collect::<Vec<_>>();
+                format!(
+                    "{}({}){}",
+                    seg.ident,
+                    inputs.join(", "),
+                    output_display(&args.output)
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("::");
+
+    match path.leading_colon {
+        Some(_) => format!("::{segments}"),
+        None => segments,
+    }
+}
+
+fn output_display(output: &ReturnType) -> String {
+    match output {
+        ReturnType::Default => String::new(),
+        ReturnType::Type(_, ty) => format!(" -> {}", type_display(ty)),
+    }
+}
+
+/// Returns `true` if the last segment of the attribute path matches `name`.
+pub fn is_attr(attr: &Attribute, name: &str) -> bool {
+    attr.path()
+        .segments
+        .last()
+        .is_some_and(|seg| seg.ident == name)
+}
+
+/// If the type is an `Option`, returns the type it wraps.
+fn split_option(ty: &Type) -> Option<Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+
+    let seg = path.path.segments.last()?;
+    if seg.ident != "Option" {
+        return None;
+    }
+
+    let PathArguments::AngleBracketed(ref args) = seg.arguments else {
+        return None;
+    };
+
+    match args.args.first() {
+        Some(GenericArgument::Type(ty)) if args.args.len() == 1 => Some(ty.clone()),
+        _ => None,
+    }
+}
+
+/// If the type is a `Result`, returns its `Ok` type and the same `Result` with `()` as the `Ok` type.
+///
+/// Matches on the last path segment so aliases such as `anyhow::Result<T>` are supported.
+pub fn split_result(ty: &Type) -> Option<(Type, Type)> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+
+    let seg = path.path.segments.last()?;
+    if seg.ident != "Result" {
+        return None;
+    }
+
+    let PathArguments::AngleBracketed(ref args) = seg.arguments else {
+        return None;
+    };
+
+    let Some(GenericArgument::Type(ok)) = args.args.first() else {
+        return None;
+    };
+    let ok = ok.clone();
+
+    let mut output = path.clone();
+    if let Some(PathArguments::AngleBracketed(args)) = output
+        .path
+        .segments
+        .last_mut()
+        .map(|seg| &mut seg.arguments)
+    {
+        args.args[0] = GenericArgument::Type(syn::parse_quote!(()));
+    }
+
+    Some((ok, Type::Path(output)))
+}
+
+/// Remove the `timeout` attribute from the attributes and parse its duration.
+fn take_timeout(attrs: &mut Vec<Attribute>) -> Option<Duration> {
+    let idx = attrs.iter().position(|attr| is_attr(attr, TIMEOUT))?;
+    let attr = attrs.remove(idx);
+    let lit = attr.parse_args::<LitStr>().unwrap_or_else(|_| {
+        abort!(
+            attr.span(),
+            "expected a duration, e.g. `#[timeout(\"5s\")]`"
+        )
+    });
+    Some(parse_duration(&lit))
+}
+
+/// Parse durations in the form of `500ms`, `30s`, `5m` or `1h`.
+fn parse_duration(lit: &LitStr) -> Duration {
+    let value = lit.value();
+    let value = value.trim();
+
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+
+    let amount = match amount.parse::<u64>() {
+        Ok(amount) => amount,
+        Err(e) if *e.kind() == IntErrorKind::PosOverflow => {
+            abort!(lit.span(), "duration too large")
+        }
+        Err(_) => abort!(lit.span(), "invalid duration, expected e.g. \"30s\""),
+    };
+
+    let millis_per_unit: u64 = match unit.trim() {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => abort!(
+            lit.span(),
+            "invalid duration unit, expected one of `ms`, `s`, `m` or `h`"
+        ),
+    };
+
+    // The duration is generated in milliseconds, see `quote_duration`
+    match amount.checked_mul(millis_per_unit) {
+        Some(0) => abort!(lit.span(), "duration must be greater than zero"),
+        Some(millis) => Duration::from_millis(millis),
+        None => abort!(lit.span(), "duration too large"),
+    }
+}
+
+/// Constructs the duration in the generated code.
+pub fn quote_duration(duration: Duration) -> proc_macro2::TokenStream {
+    let millis = u64::try_from(duration.as_millis())
+        .expect("durations are parsed from milliseconds fitting in a u64");
+    quote!(::std::time::Duration::from_millis(#millis))
+}
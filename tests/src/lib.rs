@@ -1,5 +1,29 @@
 mod poc;
 
+/// Set when a suite runs in its own process through [run_failing], making it fail on purpose.
+#[cfg(test)]
+const FAIL: &str = "SUITEST_FAIL";
+
+/// Whether the suite is expected to fail, suites behave normally otherwise.
+#[cfg(test)]
+fn failing() -> bool {
+    std::env::var_os(FAIL).is_some()
+}
+
+/// Runs the libtest test of a suite in a new process of the test binary with [FAIL] set.
+/// Returns whether the suite passed along with its output.
+#[cfg(test)]
+fn run_failing(suite: &str) -> (bool, String) {
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args([suite, "--exact", "--nocapture", "--test-threads=1"])
+        .env(FAIL, "1")
+        .output()
+        .expect("unable to run the suite");
+    let mut out = String::from_utf8_lossy(&output.stdout).into_owned();
+    out.push_str(&String::from_utf8_lossy(&output.stderr));
+    (output.status.success(), out)
+}
+
 #[cfg(test)]
 #[suitest::suite(parallel)]
 #[suitest::suite_cfg(sequential = false, verbose = false)]
@@ -41,6 +65,15 @@ pub mod parallel {
         assert_eq!(*chosen, 420);
         assert_eq!(my_stuff.qux, 69);
     }
+
+    #[test]
+    #[case(1, "works")]
+    #[case(2, "works")]
+    fn cases(n: usize, s: &str, works: (String, String), chosen: usize) {
+        assert!(n > 0);
+        assert_eq!(s, works.0);
+        assert_eq!(*chosen, 420);
+    }
 }
 
 #[cfg(test)]
@@ -180,3 +213,1821 @@ pub mod async_sequential {
         assert_eq!(my_stuff.qux, 69);
     }
 }
+
+#[cfg(test)]
+#[suitest::suite(split)]
+#[suitest::suite_cfg(split = true)]
+pub mod split {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use suitest::{after_all, after_each, before_all, before_each};
+
+    static SETUPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[before_all]
+    async fn setup() -> usize {
+        SETUPS.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    #[before_each]
+    fn beach() -> String {
+        String::from("works")
+    }
+
+    #[after_each]
+    fn aeach(works: String) {
+        assert_eq!(works, "works");
+    }
+
+    #[after_all]
+    fn teardown(setups: usize) {
+        assert_eq!(*setups, 1);
+    }
+
+    #[test]
+    fn works(works: String, setups: usize) {
+        assert_eq!(works, "works");
+        assert_eq!(*setups, 1);
+    }
+
+    #[test]
+    #[case(1)]
+    #[case(2)]
+    fn cases(n: u8, works: String) {
+        assert!(n > 0);
+        assert_eq!(works, "works");
+    }
+
+    #[test]
+    async fn works_async(works: String, setups: usize) {
+        assert_eq!(works, "works");
+        assert_eq!(*setups, 1);
+        assert_eq!(SETUPS.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(fallible)]
+pub mod fallible {
+    use std::num::ParseIntError;
+    use suitest::{before_all, before_each};
+
+    #[before_all]
+    fn setup() -> Result<(usize, String), ParseIntError> {
+        let n = "420".parse::<usize>()?;
+        let s = String::from("works");
+        Ok((n, s))
+    }
+
+    #[before_each]
+    async fn beach() -> Result<u8, ParseIntError> {
+        let magic = "69".parse::<u8>()?;
+        Ok(magic)
+    }
+
+    #[test]
+    fn works(n: usize, s: String, magic: u8) -> Result<(), ParseIntError> {
+        assert_eq!(*n, "420".parse::<usize>()?);
+        assert_eq!(s, "works");
+        assert_eq!(*magic, 69);
+        Ok(())
+    }
+
+    #[test]
+    async fn works_async(magic: u8) -> Result<(), ParseIntError> {
+        assert_eq!(*magic, "69".parse::<u8>()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(attributes)]
+pub mod attributes {
+    use suitest::before_each;
+
+    #[before_each]
+    fn beach() -> String {
+        String::from("works")
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics() {
+        panic!("works");
+    }
+
+    #[test]
+    #[should_panic(expected = "works")]
+    async fn panics_async(works: String) {
+        panic!("it {works}");
+    }
+
+    #[test]
+    #[should_panic = "works"]
+    #[case("works")]
+    #[case("still works")]
+    fn panics_cases(msg: &str) {
+        panic!("{msg}");
+    }
+
+    #[test]
+    #[ignore = "runs only when requested"]
+    fn ignored(works: String) {
+        assert_eq!(works, "works");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(split_attributes)]
+#[suitest::suite_cfg(split = true)]
+pub mod split_attributes {
+    #[test]
+    #[should_panic(expected = "works")]
+    fn panics() {
+        panic!("works");
+    }
+
+    #[test]
+    #[ignore]
+    async fn ignored() {}
+}
+
+#[cfg(test)]
+#[suitest::suite(timeouts)]
+#[suitest::suite_cfg(timeout = "10s")]
+pub mod timeouts {
+    use std::time::Duration;
+    use suitest::{before_all, before_each};
+
+    #[before_all]
+    #[timeout("1s")]
+    async fn setup() -> usize {
+        tokio::task::yield_now().await;
+        420_usize
+    }
+
+    #[before_each]
+    #[timeout("1s")]
+    fn beach() -> u8 {
+        69_u8
+    }
+
+    #[test]
+    #[timeout("1s")]
+    fn works(n: usize, magic: u8) {
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(*n, 420);
+        assert_eq!(*magic, 69);
+    }
+
+    #[test]
+    async fn works_async(n: usize) {
+        tokio::task::yield_now().await;
+        assert_eq!(*n, 420);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(retries)]
+#[suitest::suite_cfg(retries = 1)]
+pub mod retries {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use suitest::{after_all, before_each, cleanup};
+
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+    static ATTEMPTS_ASYNC: AtomicUsize = AtomicUsize::new(0);
+    static CLEANUPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[before_each]
+    fn beach() -> String {
+        String::from("works")
+    }
+
+    #[cleanup]
+    async fn cleaning() {
+        CLEANUPS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[after_all]
+    fn teardown() {
+        assert_eq!(CLEANUPS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    #[retry(2)]
+    fn flaky(works: String) {
+        assert_eq!(works, "works");
+        assert!(ATTEMPTS.fetch_add(1, Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    async fn flaky_async(works: String) {
+        assert_eq!(works, "works");
+        assert!(ATTEMPTS_ASYNC.fetch_add(1, Ordering::SeqCst) >= 1);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(retried_timeouts)]
+#[suitest::suite_cfg(retries = 1, timeout = "100ms")]
+pub mod retried_timeouts {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use suitest::{after_all, cleanup};
+
+    static HUNG: AtomicUsize = AtomicUsize::new(0);
+    static HUNG_ASYNC: AtomicUsize = AtomicUsize::new(0);
+    static CLEANUPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[cleanup]
+    fn cleaning() {
+        CLEANUPS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[after_all]
+    fn teardown() {
+        assert_eq!(HUNG.load(Ordering::SeqCst), 2);
+        assert_eq!(HUNG_ASYNC.load(Ordering::SeqCst), 2);
+        assert_eq!(CLEANUPS.load(Ordering::SeqCst), 2);
+    }
+
+    /// The first attempt hangs past the timeout of each attempt, the second one passes.
+    #[test]
+    fn hangs_once() {
+        if HUNG.fetch_add(1, Ordering::SeqCst) == 0 {
+            std::thread::sleep(Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    async fn hangs_once_async() {
+        if HUNG_ASYNC.fetch_add(1, Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(fail_fast)]
+#[suitest::suite_cfg(sequential = true, fail_fast = true)]
+pub mod fail_fast {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use suitest::{after_all, before_all};
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    #[before_all]
+    fn setup() -> usize {
+        420_usize
+    }
+
+    #[after_all]
+    fn teardown() {
+        assert_eq!(RUNS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn works(n: usize) {
+        assert_eq!(*n, 420);
+        RUNS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    async fn works_async(n: usize) {
+        tokio::task::yield_now().await;
+        assert_eq!(*n, 420);
+        RUNS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(fail_fast_parallel)]
+#[suitest::suite_cfg(sequential = false, fail_fast = true)]
+pub mod fail_fast_parallel {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use suitest::after_all;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    #[after_all]
+    fn teardown() {
+        assert_eq!(RUNS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn works() {
+        RUNS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn works_too() {
+        RUNS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    async fn works_async() {
+        RUNS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(max_concurrency)]
+#[suitest::suite_cfg(max_concurrency = 2)]
+pub mod max_concurrency {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use suitest::after_all;
+
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    fn enter() {
+        let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+        PEAK.fetch_max(running, Ordering::SeqCst);
+    }
+
+    fn exit() {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    #[after_all]
+    fn teardown() {
+        // The tests may not overlap on a loaded machine, only the limit is guaranteed
+        let peak = PEAK.load(Ordering::SeqCst);
+        assert!((1..=2).contains(&peak), "{peak} tests ran at the same time");
+    }
+
+    #[test]
+    #[case(1)]
+    #[case(2)]
+    #[case(3)]
+    #[case(4)]
+    fn works(_n: usize) {
+        enter();
+        std::thread::sleep(Duration::from_millis(20));
+        exit();
+    }
+
+    #[test]
+    #[case(1)]
+    #[case(2)]
+    #[case(3)]
+    #[case(4)]
+    async fn works_async(_n: usize) {
+        enter();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        exit();
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(multi_thread_runtime)]
+#[suitest::suite_cfg(runtime = "multi_thread", worker_threads = 2)]
+pub mod multi_thread_runtime {
+    use suitest::before_all;
+
+    #[before_all]
+    async fn setup() -> usize {
+        420_usize
+    }
+
+    #[test]
+    async fn block_in_place(n: usize) {
+        let n = tokio::task::block_in_place(|| *n);
+        assert_eq!(n, 420);
+    }
+
+    #[test]
+    async fn worker_threads() {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        assert_eq!(metrics.num_workers(), 2);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(shared_blocking)]
+#[suitest::suite_cfg(runtime = "multi_thread", worker_threads = 1)]
+pub mod shared_blocking {
+    use std::time::Duration;
+    use suitest::{after_all, before_all, before_each};
+
+    #[before_all]
+    fn setup() -> usize {
+        0_usize
+    }
+
+    // Waits for the item held by the other test without stalling the only worker
+    #[before_each]
+    fn beach(#[shared] runs: usize) {
+        *runs += 1;
+    }
+
+    #[after_all]
+    async fn check_runs(#[shared] runs: usize) {
+        assert_eq!(*runs, 2);
+    }
+
+    #[test]
+    async fn holds_shared(#[shared] runs: usize) {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(*runs > 0);
+    }
+
+    #[test]
+    async fn works() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(paused_runtime)]
+#[suitest::suite_cfg(start_paused = true)]
+pub mod paused_runtime {
+    use std::time::Duration;
+
+    #[test]
+    async fn sleeps() {
+        let start = std::time::Instant::now();
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(custom_runtime)]
+#[suitest::suite_cfg(runtime = build_runtime)]
+pub mod custom_runtime {
+    fn build_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(3)
+            .thread_name("custom")
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    async fn works() {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        assert_eq!(metrics.num_workers(), 3);
+        assert_eq!(std::thread::current().name(), Some("custom"));
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(named_state)]
+pub mod named_state {
+    use suitest::{after_all, after_each, before_all, before_each};
+
+    #[before_all]
+    #[name("primary", "replica", _)]
+    fn setup() -> (String, String, String) {
+        let primary = String::from("primary");
+        let replica = String::from("replica");
+        let unnamed = String::from("unnamed");
+        (primary, replica, unnamed)
+    }
+
+    #[before_each]
+    #[name("local")]
+    fn beach(#[state("primary")] primary: String) -> String {
+        let local = format!("{primary} local");
+        local
+    }
+
+    #[after_each]
+    fn aftch(#[state("local")] local: String) {
+        assert_eq!(local, "primary local");
+    }
+
+    #[after_all]
+    fn teardown(#[state("replica")] replica: String, unnamed: String) {
+        assert_eq!(replica, "replica");
+        assert_eq!(unnamed, "unnamed");
+    }
+
+    #[test]
+    fn works(
+        #[state("primary")] primary: String,
+        #[state("replica")] replica: String,
+        #[state("local")] local: String,
+        unnamed: String,
+    ) {
+        assert_eq!(primary, "primary");
+        assert_eq!(replica, "replica");
+        assert_eq!(local, "primary local");
+        assert_eq!(unnamed, "unnamed");
+    }
+
+    #[test]
+    async fn works_async(#[state("replica")] replica: String) {
+        assert_eq!(replica, "replica");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(mutable_state)]
+#[suitest::suite_cfg(runtime = "multi_thread")]
+pub mod mutable_state {
+    use suitest::{after_all, after_each, before_all, before_each};
+
+    #[derive(Debug, Default)]
+    pub struct Metrics {
+        tests: usize,
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Counter(usize);
+
+    #[before_all]
+    fn setup() -> (Metrics, usize) {
+        let metrics = Metrics::default();
+        let n = 420_usize;
+        (metrics, n)
+    }
+
+    #[before_each]
+    fn beach(#[shared] metrics: Metrics) -> Counter {
+        assert!(metrics.tests < 3);
+        Counter::default()
+    }
+
+    #[after_each]
+    fn aftch(counter: &mut Counter) {
+        assert_eq!(counter.0, 2);
+        counter.0 += 1;
+    }
+
+    #[after_all]
+    async fn teardown(#[shared] metrics: Metrics, n: usize) {
+        assert_eq!(metrics.tests, 3);
+        assert_eq!(*n, 420);
+    }
+
+    #[test]
+    fn works(counter: &mut Counter, #[shared] metrics: Metrics) {
+        counter.0 += 2;
+        metrics.tests += 1;
+    }
+
+    #[test]
+    fn works_again(#[shared] metrics: Metrics, counter: &mut Counter, n: usize) {
+        counter.0 += *n - 418;
+        metrics.tests += 1;
+    }
+
+    #[test]
+    async fn works_async(counter: &mut Counter, #[shared] metrics: Metrics) {
+        counter.0 += 1;
+        tokio::task::yield_now().await;
+        counter.0 += 1;
+        metrics.tests += 1;
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(optional_state)]
+pub mod optional_state {
+    use suitest::{after_all, before_all, before_each};
+
+    pub struct Cache;
+
+    #[before_all]
+    fn setup() -> usize {
+        420_usize
+    }
+
+    #[before_each]
+    #[name("local")]
+    fn beach(cache: Option<Cache>) -> String {
+        assert!(cache.is_none());
+        String::from("local")
+    }
+
+    #[after_all]
+    fn teardown(n: Option<usize>, #[state("local")] local: Option<String>) {
+        assert_eq!(n, Some(&420));
+        assert!(local.is_none());
+    }
+
+    #[test]
+    fn works(n: Option<usize>, cache: Option<Cache>, #[state("local")] local: Option<String>) {
+        assert_eq!(n, Some(&420));
+        assert!(cache.is_none());
+        assert_eq!(local.map(String::as_str), Some("local"));
+    }
+
+    #[test]
+    async fn works_async(cache: Option<Cache>) {
+        assert!(cache.is_none());
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(owned_state)]
+pub mod owned_state {
+    use suitest::{after_all, after_each, before_all, before_each};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Config {
+        url: String,
+    }
+
+    #[before_all]
+    fn setup() -> (Config, Vec<u8>) {
+        let config = Config {
+            url: String::from("localhost"),
+        };
+        let bytes = vec![4, 2, 0];
+        (config, bytes)
+    }
+
+    #[before_each]
+    fn beach() -> (UnboundedSender<usize>, UnboundedReceiver<usize>) {
+        let (tx, rx) = unbounded_channel();
+        (tx, rx)
+    }
+
+    #[after_each]
+    fn aftch(#[take] mut rx: UnboundedReceiver<usize>, #[take] tx: Option<UnboundedSender<usize>>) {
+        assert!(tx.is_none());
+        assert_eq!(rx.try_recv().unwrap(), 420);
+    }
+
+    #[after_all]
+    fn teardown(#[clone] config: Config) {
+        assert_eq!(config.url, "localhost");
+    }
+
+    #[test]
+    fn works(
+        #[clone] mut config: Config,
+        #[clone] bytes: Vec<u8>,
+        #[take] tx: UnboundedSender<usize>,
+    ) {
+        config.url.push_str(":6969");
+        assert_eq!(config.url, "localhost:6969");
+        std::thread::spawn(move || tx.send(420).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(bytes, [4, 2, 0]);
+    }
+
+    #[test]
+    async fn works_async(#[take] tx: UnboundedSender<usize>, #[clone] missing: Option<String>) {
+        assert!(missing.is_none());
+        tokio::spawn(async move { tx.send(420).unwrap() })
+            .await
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(multiple_hooks)]
+pub mod multiple_hooks {
+    use std::sync::Mutex;
+    use suitest::{after_all, after_each, before_all, before_each};
+
+    static AFTER_ALL: Mutex<Vec<&str>> = Mutex::new(vec![]);
+
+    #[before_all(order = 10)]
+    fn connect(port: usize) -> String {
+        let url = format!("localhost:{port}");
+        url
+    }
+
+    #[before_all]
+    fn setup() -> usize {
+        6969
+    }
+
+    #[before_each]
+    fn beach(log: &mut Vec<String>, url: String) {
+        log.push(format!("beach {url}"));
+    }
+
+    #[before_each(order = -1)]
+    fn log() -> Vec<String> {
+        let log = vec![];
+        log
+    }
+
+    #[after_each]
+    fn aftch_last(log: &mut Vec<String>) {
+        assert_eq!(log.as_slice(), ["beach localhost:6969", "test", "aftch"]);
+    }
+
+    #[after_each]
+    fn aftch(log: &mut Vec<String>) {
+        log.push(String::from("aftch"));
+    }
+
+    #[after_all]
+    fn teardown_last() {
+        assert_eq!(*AFTER_ALL.lock().unwrap(), ["teardown"]);
+    }
+
+    #[after_all]
+    fn teardown() {
+        AFTER_ALL.lock().unwrap().push("teardown");
+    }
+
+    #[test]
+    fn works(log: &mut Vec<String>) {
+        log.push(String::from("test"));
+    }
+
+    #[test]
+    async fn works_async(log: &mut Vec<String>) {
+        log.push(String::from("test"));
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(suite_teardown)]
+pub mod suite_teardown {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use suitest::{after_all, before_all, teardown, SuiteOutcome};
+
+    static AFTER_ALL: AtomicBool = AtomicBool::new(false);
+    static TEARDOWN: AtomicBool = AtomicBool::new(false);
+
+    #[before_all]
+    fn setup() -> String {
+        String::from("localhost")
+    }
+
+    #[after_all]
+    fn aftall() {
+        AFTER_ALL.store(true, Ordering::SeqCst);
+    }
+
+    #[teardown]
+    #[timeout("5s")]
+    fn teardown_last(outcome: &SuiteOutcome) {
+        assert!(outcome.passed());
+        assert!(TEARDOWN.load(Ordering::SeqCst));
+    }
+
+    #[teardown]
+    async fn teardown(url: String, outcome: &SuiteOutcome) {
+        assert_eq!(url, "localhost");
+        assert!(outcome.failed().is_empty());
+        assert!(outcome.errored().is_empty());
+        assert!(outcome.skipped().is_empty());
+        assert!(AFTER_ALL.load(Ordering::SeqCst));
+        TEARDOWN.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn works(url: String) {
+        assert_eq!(url, "localhost");
+    }
+
+    #[test]
+    async fn works_async() {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(split_teardown)]
+#[suitest::suite_cfg(split = true)]
+pub mod split_teardown {
+    use suitest::{teardown, SuiteOutcome};
+
+    #[teardown]
+    fn teardown(outcome: &SuiteOutcome) {
+        assert!(outcome.passed());
+    }
+
+    #[test]
+    fn works() {}
+
+    #[test]
+    async fn works_async() {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(flaky_setup)]
+#[suitest::suite_cfg(retries = 1)]
+pub mod flaky_setup {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use suitest::{before_each, teardown, SuiteOutcome};
+
+    static FAILED: AtomicBool = AtomicBool::new(false);
+
+    #[before_each]
+    fn beach() -> String {
+        if !FAILED.swap(true, Ordering::SeqCst) {
+            panic!("flaky setup");
+        }
+
+        String::from("localhost")
+    }
+
+    #[teardown]
+    fn teardown(outcome: &SuiteOutcome) {
+        assert!(outcome.passed());
+        assert!(outcome.errored().is_empty());
+    }
+
+    #[test]
+    fn works(url: String) {
+        assert_eq!(url, "localhost");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(around_each)]
+pub mod around_each {
+    use std::{future::Future, sync::Mutex, time::Duration};
+    use suitest::{after_each, around_each, before_each};
+
+    #[before_each]
+    fn beach() -> Mutex<Vec<String>> {
+        Mutex::new(vec![])
+    }
+
+    #[around_each]
+    async fn outer(test: impl Future<Output = ()>, log: Mutex<Vec<String>>) {
+        log.lock().unwrap().push(String::from("outer"));
+        tokio::time::timeout(Duration::from_secs(5), test)
+            .await
+            .unwrap();
+        log.lock().unwrap().push(String::from("outer end"));
+    }
+
+    #[around_each]
+    async fn inner(test: impl Future<Output = ()>, log: Mutex<Vec<String>>) {
+        log.lock().unwrap().push(String::from("inner"));
+        test.await;
+        log.lock().unwrap().push(String::from("inner end"));
+    }
+
+    #[after_each]
+    fn aftch(log: Mutex<Vec<String>>) {
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["outer", "inner", "test", "inner end", "outer end"]
+        );
+    }
+
+    #[test]
+    fn works(log: Mutex<Vec<String>>) {
+        log.lock().unwrap().push(String::from("test"));
+    }
+
+    #[test]
+    async fn works_async(log: Mutex<Vec<String>>) {
+        tokio::task::yield_now().await;
+        log.lock().unwrap().push(String::from("test"));
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(sync_around_each)]
+pub mod sync_around_each {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use suitest::around_each;
+
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            ACTIVE.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[around_each]
+    fn guard(test: impl FnOnce()) {
+        ACTIVE.fetch_add(1, Ordering::SeqCst);
+        let _guard = Guard;
+        test();
+    }
+
+    #[test]
+    fn works() {
+        assert!(ACTIVE.load(Ordering::SeqCst) > 0);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(test_context)]
+#[suitest::suite_cfg(retries = 1)]
+pub mod test_context {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use suitest::{after_each, cleanup, TestContext};
+
+    static FAILED: AtomicBool = AtomicBool::new(false);
+    static FAILED_ASYNC: AtomicBool = AtomicBool::new(false);
+
+    #[after_each]
+    fn aftch(ctx: TestContext) {
+        assert!(ctx.name().starts_with("works"));
+        assert_eq!(ctx.attempt(), 2);
+        assert!(ctx.panic_message().is_none());
+    }
+
+    #[cleanup]
+    async fn clean(ctx: &TestContext) {
+        assert_eq!(ctx.attempt(), 1);
+        assert_eq!(
+            ctx.panic_message(),
+            Some(format!("{} flaked", ctx.name()).as_str())
+        );
+    }
+
+    #[test]
+    #[case(1)]
+    fn works(n: usize) {
+        if !FAILED.swap(true, Ordering::SeqCst) {
+            panic!("works::case_{n} flaked");
+        }
+    }
+
+    #[test]
+    async fn works_async() {
+        if !FAILED_ASYNC.swap(true, Ordering::SeqCst) {
+            panic!("works_async flaked");
+        }
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(return_expressions)]
+pub mod return_expressions {
+    use std::num::ParseIntError;
+    use suitest::{before_all, before_each};
+
+    struct Port(u16);
+
+    #[before_all]
+    fn setup() -> (usize, String) {
+        let Ok(n) = "420".parse() else {
+            return (0, String::new());
+        };
+        (n, String::from("works"))
+    }
+
+    #[before_all(order = 1)]
+    async fn port(n: usize) -> Result<Port, ParseIntError> {
+        let port = match *n {
+            0 => return Ok(Port(0)),
+            _ => "6969".parse()?,
+        };
+        Ok(Port(port))
+    }
+
+    #[before_each]
+    fn beach(s: String) -> Result<u8, ParseIntError> {
+        if s.is_empty() {
+            "nope".parse::<u8>()?;
+        }
+        "69".parse()
+    }
+
+    #[before_each(order = 1)]
+    async fn url(port: Port) -> String {
+        match port.0 {
+            0 => String::new(),
+            port => format!("localhost:{port}"),
+        }
+    }
+
+    #[test]
+    fn works(n: usize, s: String, magic: u8, port: Port) {
+        assert_eq!(*n, 420);
+        assert_eq!(s, "localhost:6969");
+        assert_eq!(*magic, 69);
+        assert_eq!(port.0, 6969);
+    }
+
+    #[test]
+    async fn works_async(#[take] s: String) {
+        let handle = tokio::spawn(async move { s.len() });
+        assert_eq!(handle.await.unwrap(), 14);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(pattern_params)]
+pub mod pattern_params {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use suitest::{after_all, before_all, before_each};
+
+    static GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Config {
+        url: String,
+        port: u16,
+    }
+
+    struct Guard;
+
+    #[before_all]
+    fn setup() -> (Config, (String, String)) {
+        let config = Config {
+            url: String::from("localhost"),
+            port: 6969,
+        };
+        (config, (String::from("hello"), String::from("world")))
+    }
+
+    #[before_each]
+    fn beach(Config { url, .. }: Config) -> Guard {
+        assert_eq!(url, "localhost");
+        GUARDS.fetch_add(1, Ordering::SeqCst);
+        Guard
+    }
+
+    #[test]
+    fn destructures(Config { url, port }: Config, (a, b): (String, String), _: Guard) {
+        assert_eq!(url, "localhost");
+        assert_eq!(*port, 6969);
+        assert_eq!(format!("{a} {b}"), "hello world");
+    }
+
+    #[test]
+    async fn destructures_async(#[clone] (a, _): (String, String), #[take] _: Guard) {
+        assert_eq!(a, String::from("hello"));
+    }
+
+    #[after_all]
+    fn check_guards(_: Config) {
+        assert_eq!(GUARDS.load(Ordering::SeqCst), 2);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(complex_types)]
+pub mod complex_types {
+    use std::{collections::HashSet, marker::PhantomData, sync::Arc};
+    use suitest::{before_all, before_each};
+
+    trait Greeter {
+        fn greet(&self) -> String;
+    }
+
+    struct Hello;
+
+    impl Greeter for Hello {
+        fn greet(&self) -> String {
+            String::from("hello")
+        }
+    }
+
+    struct Postgres;
+
+    struct Pool<T> {
+        url: String,
+        _db: PhantomData<T>,
+    }
+
+    #[before_all]
+    fn setup() -> (Arc<dyn Greeter + Send + Sync>, Pool<Postgres>) {
+        let pool = Pool {
+            url: String::from("postgres://localhost"),
+            _db: PhantomData,
+        };
+        (Arc::new(Hello), pool)
+    }
+
+    #[before_all]
+    fn double() -> fn(u8) -> u8 {
+        |n| n * 2
+    }
+
+    #[before_each]
+    fn beach(pool: &Pool<Postgres>) -> HashSet<&'static str> {
+        assert_eq!(pool.url, "postgres://localhost");
+        HashSet::from(["static"])
+    }
+
+    #[test]
+    fn works(
+        greeter: &Arc<dyn Greeter + Send + Sync>,
+        double: fn(u8) -> u8,
+        words: &HashSet<&'static str>,
+        missing: Option<&String>,
+    ) {
+        assert_eq!(greeter.greet(), "hello");
+        assert_eq!(double(21), 42);
+        assert!(words.contains("static"));
+        assert!(missing.is_none());
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(nested)]
+pub mod nested {
+    use std::sync::Mutex;
+    use suitest::{after_all, after_each, before_all, before_each, TestContext};
+
+    static TESTS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    #[before_all]
+    fn setup() -> (String, usize) {
+        (String::from("localhost"), 0)
+    }
+
+    #[before_each]
+    fn beach() -> Vec<String> {
+        vec![String::from("parent beach")]
+    }
+
+    #[after_each]
+    fn aftch(log: &mut Vec<String>, ctx: &TestContext) {
+        assert_eq!(log.last().unwrap(), "aftch");
+        TESTS.lock().unwrap().push(ctx.name().to_string());
+    }
+
+    #[after_all]
+    fn check_tests(#[shared] runs: usize) {
+        assert_eq!(*runs, 2);
+        let mut tests = TESTS.lock().unwrap().clone();
+        tests.sort();
+        assert_eq!(
+            tests,
+            [
+                "nested::child::grandchild::works_async",
+                "nested::child::works",
+                "works"
+            ]
+        );
+    }
+
+    #[test]
+    fn works(log: &mut Vec<String>) {
+        assert_eq!(log.as_slice(), ["parent beach"]);
+        log.push(String::from("aftch"));
+    }
+
+    #[suitest::suite(child)]
+    mod child {
+        use super::*;
+
+        #[before_all]
+        fn setup(url: String) -> u16 {
+            assert_eq!(url, "localhost");
+            6969
+        }
+
+        #[before_each]
+        fn beach(log: &mut Vec<String>, port: u16) {
+            log.push(format!("child beach {port}"));
+        }
+
+        #[after_each]
+        fn aftch(log: &mut Vec<String>) {
+            log.push(String::from("aftch"));
+        }
+
+        #[after_all]
+        fn check_runs(#[shared] runs: usize) {
+            *runs += 1;
+        }
+
+        #[test]
+        fn works(url: String, port: u16, log: &mut Vec<String>) {
+            assert_eq!(format!("{url}:{port}"), "localhost:6969");
+            assert_eq!(log.as_slice(), ["parent beach", "child beach 6969"]);
+        }
+
+        #[suitest::suite(grandchild)]
+        mod grandchild {
+            #[test]
+            async fn works_async(url: String, port: u16, #[shared] runs: usize) {
+                assert_eq!(url, "localhost");
+                assert_eq!(*port, 6969);
+                *runs += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    pub const TEMP_DIR: &str = "suitest";
+
+    #[suitest::fixtures]
+    pub mod database {
+        use suitest::{after_all, before_all, before_each};
+
+        pub struct Pool {
+            pub url: String,
+        }
+
+        pub fn begin(pool: &Pool) -> String {
+            format!("BEGIN {}", pool.url)
+        }
+
+        #[before_all]
+        fn connect() -> Pool {
+            Pool {
+                url: String::from("postgres://localhost"),
+            }
+        }
+
+        #[before_each]
+        fn transaction(pool: Pool) -> Vec<String> {
+            vec![begin(pool)]
+        }
+
+        #[after_all]
+        fn close(pool: Pool) {
+            assert_eq!(pool.url, "postgres://localhost");
+        }
+    }
+
+    #[suitest::fixtures]
+    pub mod tempdir {
+        use super::TEMP_DIR;
+        use std::path::PathBuf;
+        use suitest::before_all;
+
+        #[before_all]
+        async fn create() -> PathBuf {
+            std::env::temp_dir().join(TEMP_DIR)
+        }
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(use_fixtures, use = crate::fixtures::database, use = crate::fixtures::tempdir)]
+pub mod use_fixtures {
+    use crate::fixtures::database::Pool;
+    use std::path::PathBuf;
+    use suitest::{after_each, before_all};
+
+    #[before_all]
+    fn setup(pool: Pool, dir: PathBuf) -> String {
+        format!("{}/{}", pool.url, dir.display())
+    }
+
+    #[after_each]
+    fn commit(queries: &mut Vec<String>) {
+        queries.push(String::from("COMMIT"));
+    }
+
+    #[test]
+    fn queries(pool: Pool, queries: &mut Vec<String>) {
+        assert_eq!(queries.as_slice(), ["BEGIN postgres://localhost"]);
+        queries.push(format!("SELECT * FROM {}", pool.url));
+    }
+
+    #[test]
+    async fn composes(url: String, dir: PathBuf) {
+        assert!(url.starts_with("postgres://localhost/"));
+        assert!(dir.ends_with("suitest"));
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(reuse_fixtures, use = fixtures::database, use = self::fixtures::tempdir)]
+pub mod reuse_fixtures {
+    use crate::fixtures::{database::Pool, TEMP_DIR};
+    use std::path::PathBuf;
+
+    #[test]
+    fn reuses(pool: Pool, queries: Vec<String>, dir: PathBuf) {
+        assert_eq!(pool.url, "postgres://localhost");
+        assert_eq!(queries.len(), 1);
+        assert!(dir.ends_with(TEMP_DIR));
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(type_aliases, use = fixtures::database)]
+pub mod type_aliases {
+    use crate::fixtures::database;
+    use config::Config;
+    use suitest::{before_all, before_each};
+
+    type Url = String;
+
+    mod config {
+        pub struct Config {
+            pub retries: usize,
+        }
+    }
+
+    #[before_all]
+    fn setup() -> Url {
+        String::from("postgres://localhost")
+    }
+
+    #[before_each]
+    fn configure() -> config::Config {
+        Config { retries: 3 }
+    }
+
+    #[test]
+    fn resolves_aliases(url: String, same: Url) {
+        assert_eq!(url, "postgres://localhost");
+        assert_eq!(same, "postgres://localhost");
+    }
+
+    #[test]
+    fn resolves_paths(config: &mut Config, pool: database::Pool) {
+        config.retries += 1;
+        assert_eq!(config.retries, 4);
+        assert_eq!(pool.url, "postgres://localhost");
+    }
+
+    #[suitest::suite(child)]
+    mod child {
+        #[test]
+        fn resolves_inherited(url: std::string::String, config: super::Config) {
+            assert_eq!(url, "postgres://localhost");
+            assert_eq!(config.retries, 3);
+        }
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(exclusive_access)]
+pub mod exclusive_access {
+    use suitest::{around_each, before_all, before_each};
+
+    #[before_all]
+    fn setup() -> usize {
+        10
+    }
+
+    #[before_each]
+    #[name(_, "copy")]
+    fn beach() -> (usize, usize) {
+        (0, 0)
+    }
+
+    #[around_each]
+    fn wrap(test: impl FnOnce(), #[state("copy")] copy: usize) {
+        assert_eq!(*copy, 0);
+        test();
+    }
+
+    #[test]
+    fn borrows_other_items(count: &mut usize, #[state("copy")] copy: usize) {
+        *count += 1;
+        assert_eq!(*count, 1);
+        assert_eq!(*copy, 0);
+    }
+
+    #[test]
+    fn takes_other_items(#[take] count: usize, #[shared] global: usize) {
+        assert_eq!(count, 0);
+        assert_eq!(*global, 10);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(teardown_panics)]
+pub mod teardown_panics {
+    use crate::failing;
+    use suitest::{before_all, teardown};
+
+    pub struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            println!("connection dropped");
+        }
+    }
+
+    #[before_all]
+    fn connect() -> Connection {
+        Connection
+    }
+
+    #[teardown]
+    fn disconnect(_: Connection) {
+        println!("disconnect ran");
+    }
+
+    #[teardown]
+    fn panics() {
+        if failing() {
+            panic!("teardown panicked");
+        }
+    }
+
+    #[test]
+    fn works(_: Connection) {}
+}
+
+#[cfg(test)]
+#[suitest::suite(fail_fast_skips)]
+#[suitest::suite_cfg(sequential = true, fail_fast = true)]
+pub mod fail_fast_skips {
+    use crate::failing;
+    use suitest::{teardown, SuiteOutcome};
+
+    #[teardown]
+    fn report(outcome: &SuiteOutcome) {
+        println!("skipped: {:?}", outcome.skipped());
+    }
+
+    #[test]
+    fn fails() {
+        assert!(!failing(), "fails on purpose");
+    }
+
+    #[test]
+    fn after_failure() {
+        println!("after_failure ran");
+    }
+
+    #[test]
+    async fn after_failure_async() {
+        println!("after_failure_async ran");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(timeouts_exceeded)]
+pub mod timeouts_exceeded {
+    use crate::failing;
+    use std::time::Duration;
+    use suitest::{cleanup, TestContext};
+
+    /// How long the tests take, exceeding their timeout only when failing on purpose.
+    fn duration() -> Duration {
+        if failing() {
+            Duration::from_secs(2)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    #[cleanup]
+    fn report(ctx: &TestContext) {
+        println!(
+            "cleanup ran for {}: {}",
+            ctx.name(),
+            ctx.panic_message().unwrap_or_default()
+        );
+    }
+
+    #[test]
+    #[timeout("50ms")]
+    fn slow() {
+        std::thread::sleep(duration());
+    }
+
+    #[test]
+    #[timeout("50ms")]
+    async fn slow_async() {
+        tokio::time::sleep(duration()).await;
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(returned_errors)]
+pub mod returned_errors {
+    use crate::failing;
+    use std::num::ParseIntError;
+
+    /// Parses a number, which is invalid only when failing on purpose.
+    pub fn parse() -> Result<u32, ParseIntError> {
+        if failing() { "not a number" } else { "1" }.parse()
+    }
+
+    #[test]
+    fn fails() -> Result<(), ParseIntError> {
+        parse()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(setup_returns_error)]
+pub mod setup_returns_error {
+    use crate::returned_errors::parse;
+    use std::num::ParseIntError;
+    use suitest::before_all;
+
+    #[before_all]
+    fn setup() -> Result<u32, ParseIntError> {
+        parse()
+    }
+
+    #[test]
+    fn works(n: u32) {
+        assert_eq!(*n, 1);
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(before_all_panics)]
+pub mod before_all_panics {
+    use crate::failing;
+    use suitest::{before_all, teardown, SuiteOutcome};
+
+    pub struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            println!("connection dropped");
+        }
+    }
+
+    #[before_all]
+    fn connect() -> Connection {
+        Connection
+    }
+
+    #[before_all]
+    fn migrate(_: Connection) {
+        if failing() {
+            panic!("migration panicked");
+        }
+    }
+
+    #[teardown]
+    fn report(outcome: &SuiteOutcome) {
+        println!(
+            "teardown ran, failed: {:?}, errored: {:?}",
+            outcome.failed(),
+            outcome.errored()
+        );
+    }
+
+    #[test]
+    fn works(_: Connection) {
+        println!("works ran");
+    }
+
+    #[test]
+    async fn works_async(_: Connection) {
+        println!("works_async ran");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(before_each_panics)]
+#[suitest::suite_cfg(sequential = true)]
+pub mod before_each_panics {
+    use crate::failing;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use suitest::{before_each, teardown, SuiteOutcome};
+
+    static STARTED: AtomicBool = AtomicBool::new(false);
+
+    pub struct Transaction;
+
+    impl Drop for Transaction {
+        fn drop(&mut self) {
+            println!("transaction dropped");
+        }
+    }
+
+    /// Panics for the first test only when failing on purpose.
+    #[before_each]
+    fn begin() -> Transaction {
+        if failing() && !STARTED.swap(true, Ordering::SeqCst) {
+            panic!("begin panicked");
+        }
+        Transaction
+    }
+
+    #[teardown]
+    fn report(outcome: &SuiteOutcome) {
+        println!(
+            "teardown ran, failed: {:?}, errored: {:?}",
+            outcome.failed(),
+            outcome.errored()
+        );
+    }
+
+    #[test]
+    fn errors(_: Transaction) {
+        println!("errors ran");
+    }
+
+    #[test]
+    fn works(_: Transaction) {
+        println!("works ran");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(retries_exhausted)]
+#[suitest::suite_cfg(retries = 2)]
+pub mod retries_exhausted {
+    use crate::failing;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use suitest::{cleanup, TestContext};
+
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+    #[cleanup]
+    fn report(ctx: &TestContext) {
+        println!("cleanup ran for attempt {}", ctx.attempt());
+    }
+
+    #[test]
+    fn fails() {
+        let attempt = ATTEMPTS.fetch_add(1, Ordering::SeqCst) + 1;
+        assert!(!failing(), "attempt {attempt} failed");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(around_each_skips)]
+pub mod around_each_skips {
+    use crate::failing;
+    use std::future::Future;
+    use suitest::around_each;
+
+    /// Drops the test instead of running it when failing on purpose.
+    #[around_each]
+    async fn skip(test: impl Future<Output = ()>) {
+        if !failing() {
+            test.await;
+        }
+    }
+
+    #[test]
+    fn must_fail() {
+        assert!(!failing(), "must_fail ran");
+    }
+
+    #[test]
+    async fn must_fail_async() {
+        assert!(!failing(), "must_fail_async ran");
+    }
+}
+
+#[cfg(test)]
+#[suitest::suite(around_each_skips_sync)]
+pub mod around_each_skips_sync {
+    use crate::failing;
+    use suitest::around_each;
+
+    /// Drops the test instead of running it when failing on purpose.
+    #[around_each]
+    fn skip(test: impl FnOnce()) {
+        if !failing() {
+            test();
+        }
+    }
+
+    #[test]
+    fn must_fail() {
+        assert!(!failing(), "must_fail ran");
+    }
+}
+
+/// Asserts the outcome of the suites failing on purpose, each running in its own process.
+#[cfg(test)]
+mod failures {
+    use crate::run_failing;
+
+    /// Returns the position of `msg` in the output, failing if it is missing.
+    fn position(output: &str, msg: &str) -> usize {
+        output
+            .find(msg)
+            .unwrap_or_else(|| panic!("'{msg}' missing from output:\n{output}"))
+    }
+
+    #[test]
+    fn teardown_panics() {
+        let (passed, output) = run_failing("teardown_panics::teardown_panics");
+        assert!(!passed);
+        position(&output, "teardown panicked");
+        let disconnected = position(&output, "disconnect ran");
+        let dropped = position(&output, "connection dropped");
+        assert!(disconnected < dropped);
+    }
+
+    #[test]
+    fn before_all_panics() {
+        let (passed, output) = run_failing("before_all_panics::before_all_panics");
+        assert!(!passed);
+        position(&output, "migration panicked");
+        position(&output, "setup failed in before_all");
+        let teardown = position(
+            &output,
+            r#"teardown ran, failed: [], errored: ["works", "works_async"]"#,
+        );
+        let dropped = position(&output, "connection dropped");
+        assert!(teardown < dropped);
+        assert!(!output.contains("works ran"), "{output}");
+        assert!(!output.contains("works_async ran"), "{output}");
+    }
+
+    #[test]
+    fn before_each_panics() {
+        let (passed, output) = run_failing("before_each_panics::before_each_panics");
+        assert!(!passed);
+        position(&output, "begin panicked");
+        position(&output, "setup failed in before_each for test errors");
+        position(&output, "works ran");
+        let teardown = position(&output, r#"teardown ran, failed: [], errored: ["errors"]"#);
+        let dropped = position(&output, "transaction dropped");
+        assert!(teardown < dropped);
+        assert!(!output.contains("errors ran"), "{output}");
+    }
+
+    #[test]
+    fn retries_exhausted() {
+        let (passed, output) = run_failing("retries_exhausted::retries_exhausted");
+        assert!(!passed);
+        position(&output, "fails failed on attempt 1, retrying");
+        position(&output, "fails failed on attempt 2, retrying");
+        assert!(!output.contains("failed on attempt 3"), "{output}");
+        // The panic of the last attempt fails the test
+        position(&output, "attempt 3 failed");
+        position(&output, "fails ... x");
+        for attempt in 1..=3 {
+            position(&output, &format!("cleanup ran for attempt {attempt}\n"));
+        }
+        assert_eq!(output.matches("cleanup ran").count(), 3, "{output}");
+    }
+
+    #[test]
+    fn around_each_skips() {
+        let (passed, output) = run_failing("around_each_skips::around_each_skips");
+        assert!(!passed);
+        assert_eq!(
+            output
+                .matches("around_each hook `skip` did not run the test")
+                .count(),
+            2,
+            "{output}"
+        );
+        position(&output, "must_fail ... x");
+        position(&output, "must_fail_async ... x");
+        assert!(!output.contains("must_fail ran"), "{output}");
+        assert!(!output.contains("must_fail_async ran"), "{output}");
+    }
+
+    #[test]
+    fn around_each_skips_sync() {
+        let (passed, output) = run_failing("around_each_skips_sync::around_each_skips_sync");
+        assert!(!passed);
+        position(&output, "around_each hook `skip` did not run the test");
+        position(&output, "must_fail ... x");
+        assert!(!output.contains("must_fail ran"), "{output}");
+    }
+
+    #[test]
+    fn fail_fast_skips() {
+        let (passed, output) = run_failing("fail_fast_skips::fail_fast_skips");
+        assert!(!passed);
+        position(&output, "fails on purpose");
+        position(
+            &output,
+            "skipped 2 test(s) after the first failure: after_failure, after_failure_async",
+        );
+        position(
+            &output,
+            r#"skipped: ["after_failure", "after_failure_async"]"#,
+        );
+        assert!(!output.contains("after_failure ran"), "{output}");
+        assert!(!output.contains("after_failure_async ran"), "{output}");
+    }
+
+    #[test]
+    fn returned_errors() {
+        let (passed, output) = run_failing("returned_errors::returned_errors");
+        assert!(!passed);
+        position(
+            &output,
+            "fails returned an error: ParseIntError { kind: InvalidDigit }",
+        );
+        position(&output, "fails ... x");
+    }
+
+    #[test]
+    fn setup_returns_error() {
+        let (passed, output) = run_failing("setup_returns_error::setup_returns_error");
+        assert!(!passed);
+        position(
+            &output,
+            "setup returned an error: ParseIntError { kind: InvalidDigit }",
+        );
+    }
+
+    #[test]
+    fn timeouts_exceeded() {
+        let (passed, output) = run_failing("timeouts_exceeded::timeouts_exceeded");
+        assert!(!passed);
+        position(&output, "slow ... x");
+        position(&output, "slow_async ... x");
+        position(&output, "cleanup ran for slow: slow timed out after 50ms");
+        position(
+            &output,
+            "cleanup ran for slow_async: slow_async timed out after 50ms",
+        );
+    }
+}
@@ -1,4 +1,4 @@
-#![allow(dead_code, unused_imports)]
+#![allow(dead_code, unused_imports, static_mut_refs)]
 
 /// Module used as a reference to see the internals of `suitest`.
 pub mod prototype_with_maps {